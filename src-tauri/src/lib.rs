@@ -1,16 +1,19 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use base64::Engine;
 use exif::{In, Tag, Value};
+use notify_debouncer_mini::{new_debouncer, notify, DebounceEventResult, Debouncer};
 use once_cell::sync::OnceCell;
+use r2d2_sqlite::SqliteConnectionManager;
 use rayon::prelude::*;
-use rusqlite::{params, Connection, Result as SqlResult};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Result as SqlResult};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
     fs,
     path::Path,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc, Mutex,
     },
     time::{SystemTime, UNIX_EPOCH},
@@ -18,424 +21,7351 @@ use std::{
 use tauri::{Emitter, Manager};
 use walkdir::WalkDir;
 
-// single global DB connection shared across threads
+// single global write connection shared across threads
 static DB: OnceCell<Mutex<Connection>> = OnceCell::new();
 
+// read-only connections so cache lookups from scan workers run in parallel under WAL
+// instead of queueing behind the write lock
+static READ_POOL: OnceCell<r2d2::Pool<SqliteConnectionManager>> = OnceCell::new();
+
 fn db() -> &'static Mutex<Connection> {
     DB.get().expect("DB not initialized")
 }
 
-const IMAGE_EXTS: &[&str] = &["jpg", "jpeg", "png", "webp"];
-const PHASH_THRESHOLD: u32 = 5; // max hamming distance to consider two images "similar"
+fn read_db() -> Result<r2d2::PooledConnection<SqliteConnectionManager>, r2d2::Error> {
+    READ_POOL.get().expect("DB not initialized").get()
+}
+
+// scanned rows go to a writer thread with its own connection, so hashing workers never
+// wait on the DB lock. everything else still writes through db()
+static WRITER: OnceCell<std::sync::mpsc::Sender<WriterMsg>> = OnceCell::new();
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// the persisted similarity index (similarity_index.json next to the DB) and the copy of
+// it last loaded or built, so re-querying at a new threshold doesn't touch the disk
+static SIMILARITY_INDEX_PATH: OnceCell<std::path::PathBuf> = OnceCell::new();
+static SIMILARITY_INDEX: Mutex<Option<SimilarityIndex>> = Mutex::new(None);
+// offline city lookup, parsed from the bundled GeoNames table on first use (~1s)
+static GEOCODER: OnceCell<reverse_geocoder::ReverseGeocoder> = OnceCell::new();
+
+enum WriterMsg {
+    Image(Box<ImageInfo>),
+    Quarantine { path: String, reason: String },
+    // commit whatever is pending, then report the first error since the last flush
+    Flush(std::sync::mpsc::Sender<Result<(), String>>),
+}
+
+// set by cancel_scan, checked by the scan workers before each file
+static SCAN_CANCELLED: AtomicBool = AtomicBool::new(false);
+// set by cancel_thumbnails, checked by pregenerate_thumbnails before each image
+static THUMBNAILS_CANCELLED: AtomicBool = AtomicBool::new(false);
+// true while scan_folder runs, so maintenance doesn't compete with it for the DB
+static SCAN_RUNNING: AtomicBool = AtomicBool::new(false);
+
+// the active folder watcher, if any. dropping it stops the watch
+static WATCHER: Mutex<Option<Debouncer<notify::RecommendedWatcher>>> = Mutex::new(None);
+
+// recent reversible file operations, newest last. deletes are permanent and aren't logged
+static OPERATION_LOG: Mutex<std::collections::VecDeque<FileOperation>> =
+    Mutex::new(std::collections::VecDeque::new());
+const OPERATION_LOG_LEN: usize = 20;
+
+// animated GIFs are hashed on their first frame, which is what load_from_memory decodes
+const IMAGE_EXTS: &[&str] = &[
+    "jpg", "jpeg", "png", "webp", "heic", "heif", "avif", "jxl", "gif", "bmp", "tif", "tiff",
+    "cr2", "nef", "arw", "dng",
+];
+// decoded through their embedded JPEG preview rather than the sensor data
+const RAW_EXTS: &[&str] = &["cr2", "nef", "arw", "dng"];
+// folders that NAS boxes and operating systems fill with their own thumbnails and junk,
+// matched case-insensitively. dot-folders like .Trashes are caught by the dot rule
+const SYSTEM_DIRS: &[&str] = &[
+    "@eadir",
+    "#recycle",
+    "#snapshot",
+    "$recycle.bin",
+    "system volume information",
+    "lost+found",
+];
+// lowercased filename fragments phones and desktops give screenshots, in a few languages
+const SCREENSHOT_NAME_PATTERNS: &[&str] = &[
+    "screenshot",
+    "screen shot",
+    "screen_shot",
+    "bildschirmfoto",
+    "captura de pantalla",
+    "capture d’écran",
+    "capture d'écran",
+    "schermafbeelding",
+    "schermata",
+];
+// common phone and monitor resolutions, in portrait. a capture is exactly one of these
+const SCREEN_SIZES: &[(u32, u32)] = &[
+    (640, 1136),
+    (750, 1334),
+    (828, 1792),
+    (1080, 1920),
+    (1080, 2340),
+    (1080, 2400),
+    (1125, 2436),
+    (1170, 2532),
+    (1179, 2556),
+    (1242, 2208),
+    (1242, 2688),
+    (1284, 2778),
+    (1290, 2796),
+    (1440, 2560),
+    (1440, 3040),
+    (1440, 3120),
+    (1440, 3200),
+    (1536, 2048),
+    (1668, 2388),
+    (2048, 2732),
+    (768, 1366),
+    (800, 1280),
+    (900, 1440),
+    (1050, 1680),
+    (1200, 1920),
+    (1600, 2560),
+    (1800, 2880),
+    (1964, 3024),
+    (2160, 3840),
+];
+const SCAN_RESULT_BATCH: usize = 50; // images per "scan-result" event while scanning
+const CACHE_BATCH_SIZE: usize = 500; // rows per transaction when writing scan results
+const WATCH_DEBOUNCE_MS: u64 = 1000; // quiet period before a burst of file events is handled
+const HASH_READ_BUF_SIZE: usize = 256 * 1024; // read buffer when streaming files through SHA-256
+const BURST_MAX_GAP_SECS: i64 = 2; // default gap between frames that still counts as one burst
+const BLUR_THRESHOLD: f64 = 100.0; // default Laplacian variance below which a shot is "blurry"
+const BLUR_ANALYSIS_EDGE: u32 = 1024; // images are downscaled to this before blur scoring
+const EXPOSURE_ANALYSIS_EDGE: u32 = 512; // images are downscaled to this before exposure stats
+const CLIPPED_BLACK_LUMA: u8 = 4; // luma at or below this counts as crushed shadows
+const CLIPPED_WHITE_LUMA: u8 = 251; // luma at or above this counts as blown highlights
+
+// "too dark": a mean luminance (0..1) below TOO_DARK_MEAN, or more than TOO_DARK_CLIPPED of
+// the pixels crushed to black. "blown out": a mean above BLOWN_OUT_MEAN, or more than
+// BLOWN_OUT_CLIPPED of the pixels clipped to white. a night sky or a snowfield can trip
+// these legitimately, so they flag shots for a look rather than for deletion
+const TOO_DARK_MEAN: f64 = 0.15;
+const TOO_DARK_CLIPPED: f64 = 0.25;
+const BLOWN_OUT_MEAN: f64 = 0.85;
+const BLOWN_OUT_CLIPPED: f64 = 0.05;
+const COLOR_BINS: usize = 4; // histogram bins per RGB channel in a color signature
+const COLOR_SAMPLE_EDGE: u32 = 64; // images are downscaled to this before color analysis
+const COLOR_TOLERANCE: f64 = 0.25; // default histogram distance (0..1) to cluster two images
+const LOCATION_RADIUS_KM: f64 = 1.0; // default distance between shots in one location cluster
+const THUMBNAIL_QUALITY: u8 = 80; // JPEG quality for generated thumbnails
+const DIFF_ASPECT_TOLERANCE: f64 = 0.01; // relative aspect ratio difference diff_images accepts
+const DIFF_PIXEL_TOLERANCE: u8 = 16; // channel difference below which a pixel counts as unchanged
+const DIFF_PREVIEW_EDGE: u32 = 2048; // the heatmap is shrunk to fit within this
+const SHEET_PADDING: u32 = 12; // gap around and between contact sheet cells, px
+const SHEET_CAPTION_PX: f32 = 14.0; // caption font size on contact sheets
+const SHEET_MAX_HEIGHT: u32 = 16384; // taller sheets are split into numbered pages
+const PHASH_THRESHOLD: u32 = 5; // default max hamming distance to consider two images "similar"
+const PHASH_BITS: u32 = 64; // dHash is 64 bits, so any distance above this is meaningless
+
+// default max aHash distance for two images to stay grouped. aHash is coarse on its own,
+// it only has to veto the dHash matches that look nothing alike, so this is looser
+const AHASH_THRESHOLD: u32 = 10;
+// settings the backend itself reads, as defaults for commands called without them
+const SETTING_SIMILARITY_THRESHOLD: &str = "similarity_threshold";
+const SETTING_AHASH_THRESHOLD: &str = "ahash_threshold";
+const SETTING_DOCUMENT_THRESHOLD: &str = "document_similarity_threshold";
+const SETTING_EXTENSIONS: &str = "extensions"; // replaces IMAGE_EXTS, see set_extensions
+const HASH_NORMALIZE_EDGE: u32 = 256; // intermediate size for HashAlgo::DhashNormalized
+
+// max hamming distance between two document hashes, used in place of the photo threshold.
+// noisy rescans of one page land within ~4 of each other, different pages well past 8
+const DOCUMENT_THRESHOLD: u32 = 8;
+const DOCUMENT_DENOISE_SIGMA: f32 = 1.0; // blur against scanner noise and dust before hashing
+const DOCUMENT_PAPER_RADIUS: u32 = 3; // local max over this reaches paper between text lines
+const DOCUMENT_PAPER_SIGMA: f32 = 8.0; // smooths that into the page's shading
+const TILE_GRID: u32 = 3; // crop-resistant hashing covers the image with TILE_GRID^2 tiles
+const TILE_MIN_DETAIL: u32 = 4; // tiles with fewer set (or unset) bits are flat, e.g. sky
+const CROP_MATCH_FRACTION: f64 = 0.3; // default share of tiles that must match for a crop
+const MAX_IMAGE_PIXELS: u64 = 100_000_000; // default decode limit, larger files are rejected
+const DECODE_BYTES_PER_PIXEL: u64 = 8; // decoder allocation budget per pixel, an RGBA16 buffer
+const MIN_IMAGE_BYTES: u64 = 26; // smaller than any real image file, a 1x1 GIF is about this
+const METADATA_SCAN_BYTES: u64 = 256 * 1024; // how far into a file embedded XMP/IPTC is looked for
+const MAX_ARCHIVE_ENTRY_BYTES: u64 = 512 * 1024 * 1024; // zip entries inflating past this are refused
+
+// captions and keywords from an .xmp sidecar, embedded XMP or embedded IPTC, in that
+// order of preference field by field
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ImageMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub keywords: Vec<String>,
+    pub copyright: Option<String>,
+    // mtime of the sidecar this came from. editing a sidecar doesn't touch the image, so
+    // this is what tells a rescan the cached metadata is stale
+    pub sidecar_modified_at: Option<i64>,
+}
+
+impl ImageMetadata {
+    fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.description.is_none()
+            && self.keywords.is_empty()
+            && self.copyright.is_none()
+    }
+
+    // fills whatever this is missing from a lower-priority source
+    fn or(self, other: ImageMetadata) -> ImageMetadata {
+        ImageMetadata {
+            title: self.title.or(other.title),
+            description: self.description.or(other.description),
+            keywords: if self.keywords.is_empty() {
+                other.keywords
+            } else {
+                self.keywords
+            },
+            copyright: self.copyright.or(other.copyright),
+            sidecar_modified_at: self.sidecar_modified_at.or(other.sidecar_modified_at),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ExifData {
     pub date: Option<i64>,
+    pub date_ms: Option<i64>, // date in milliseconds, with the SubSecTime fraction when present
     pub make: Option<String>,
     pub model: Option<String>,
     pub width: Option<u32>,
     pub height: Option<u32>,
+    pub lat: Option<f64>,         // signed decimal degrees, negative = south
+    pub lon: Option<f64>,         // signed decimal degrees, negative = west
+    pub orientation: Option<u32>, // EXIF orientation 1-8, 1 = upright
+    pub iso: Option<u32>,
+    pub f_number: Option<f64>,
+    pub exposure_time: Option<String>, // as photographers write it, e.g. "1/250" or "2"
+    pub focal_length: Option<f64>,     // mm
+    pub lens_model: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ImageInfo {
-    pub path: String,
-    pub name: String,
-    pub size: u64,
-    pub created_at: i64,
-    pub modified_at: i64,
-    pub phash: Option<String>,
-    pub sha1: Option<String>,
-    pub exif: Option<ExifData>,
+// one field of a file's EXIF, as get_exif_raw reports it
+#[derive(Debug, Serialize, Clone)]
+pub struct ExifField {
+    pub tag_name: String, // e.g. "DateTimeOriginal", or "Tag(Exif, 42035)" for unknown tags
+    pub ifd: String,      // the image it describes and the IFD it's in, e.g. "primary/gps"
+    pub value_display: String, // with units where the tag has them, e.g. "1/250 s"
 }
 
-#[derive(Debug, Serialize, Clone)]
-struct ScanProgress {
-    current: usize,
-    total: usize,
+// what a scan does when a file times out
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutPolicy {
+    #[default]
+    Skip, // report the file and carry on
+    Abort, // stop the scan like cancel_scan would, keeping what's done
 }
 
-fn init_db(app_data_dir: &str) -> SqlResult<Connection> {
-    let db_path = format!("{}/image_cache.db", app_data_dir);
-    let conn = Connection::open(&db_path)?;
+// which perceptual hash produced an image's phash. hashes from different algorithms
+// aren't comparable, so this is stored alongside every hash
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    #[default]
+    Dhash, // gradient hash — fast, the original algorithm
+    Phash, // DCT hash — slower, more robust to brightness changes and recompression
+    // dHash of the image pre-scaled to HASH_NORMALIZE_EDGE — matches resized exports
+    #[serde(rename = "dhash_normalized")]
+    DhashNormalized,
+    // dHash of the ink on a scanned page, with noise and uneven lighting taken out. picked
+    // by ScanOptions::content_type rather than asked for directly
+    #[serde(rename = "dhash_document")]
+    DhashDocument,
+}
 
-    // WAL mode gives much better concurrent read performance
-    conn.execute_batch("PRAGMA journal_mode=WAL;")?;
-    conn.execute_batch("PRAGMA synchronous=NORMAL;")?;
-    conn.execute_batch(
-        "CREATE TABLE IF NOT EXISTS images (
-            path        TEXT PRIMARY KEY,
-            name        TEXT NOT NULL,
-            size        INTEGER NOT NULL,
-            created_at  INTEGER NOT NULL,
-            modified_at INTEGER NOT NULL,
-            phash       TEXT,
-            sha1        TEXT,
-            exif_json   TEXT
-        );
-        CREATE INDEX IF NOT EXISTS idx_phash ON images(phash);
-        CREATE INDEX IF NOT EXISTS idx_sha1  ON images(sha1);",
-    )?;
+// what a scan is looking at. documents hash with HashAlgo::DhashDocument and group with
+// DOCUMENT_THRESHOLD, whatever hash_algo says
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    #[default]
+    Photo,
+    Document,
+}
 
-    println!("DB initialized at: {}", db_path);
-    Ok(conn)
+// how much a scan reads besides pixels. ordered, so a row cached at one level satisfies
+// any scan asking for that level or less
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataLevel {
+    None, // nothing, not even dimensions
+    // dimensions from the image header only. EXIF isn't parsed, so there's no
+    // orientation either and rotated photos are hashed as stored
+    Dimensions,
+    #[default]
+    Full, // EXIF, XMP/IPTC and dimensions
 }
 
-// cache lookup — validates against mtime and size so stale entries don't get returned
-fn cache_get(path: &str, mtime: i64, size: u64) -> Option<ImageInfo> {
-    let conn = db().lock().unwrap();
-    conn.query_row(
-        "SELECT path, name, size, created_at, modified_at, phash, sha1, exif_json
-         FROM images WHERE path = ?1 AND modified_at = ?2 AND size = ?3",
-        params![path, mtime, size as i64],
-        |row| {
-            let exif_json: Option<String> = row.get(7)?;
-            let exif = exif_json.and_then(|j| serde_json::from_str(&j).ok());
-            Ok(ImageInfo {
-                path: row.get(0)?,
-                name: row.get(1)?,
-                size: row.get::<_, i64>(2)? as u64,
-                created_at: row.get(3)?,
-                modified_at: row.get(4)?,
-                phash: row.get(5)?,
-                sha1: row.get(6)?,
-                exif,
-            })
-        },
-    )
-    .ok()
+impl MetadataLevel {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetadataLevel::None => "none",
+            MetadataLevel::Dimensions => "dimensions",
+            MetadataLevel::Full => "full",
+        }
+    }
+
+    // NULL for rows written before the column existed, which all had full metadata
+    fn from_db(s: Option<&str>) -> Self {
+        match s {
+            Some("none") => MetadataLevel::None,
+            Some("dimensions") => MetadataLevel::Dimensions,
+            _ => MetadataLevel::Full,
+        }
+    }
 }
 
-fn cache_set(img: &ImageInfo) -> SqlResult<()> {
-    let conn = db().lock().unwrap();
-    let exif_json = img.exif.as_ref().and_then(|e| serde_json::to_string(e).ok());
-    conn.execute(
-        "INSERT OR REPLACE INTO images
-            (path, name, size, created_at, modified_at, phash, sha1, exif_json)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-        params![
-            img.path,
-            img.name,
-            img.size as i64,
-            img.created_at,
-            img.modified_at,
-            img.phash,
-            img.sha1,
-            exif_json,
-        ],
-    )?;
-    Ok(())
+// why two images' perceptual hashes can't be compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashMismatch {
+    Missing, // at least one side has no phash (undecodable, or a metadata-only scan)
+    DifferentAlgorithms(HashAlgo, HashAlgo),
 }
 
-// removes cache rows for files that no longer exist in the scanned folder
-fn cache_prune(valid_paths: &[String]) -> SqlResult<usize> {
-    let conn = db().lock().unwrap();
-    conn.execute_batch(
-        "CREATE TEMP TABLE IF NOT EXISTS valid_paths (path TEXT PRIMARY KEY);
-         DELETE FROM valid_paths;",
-    )?;
-    {
-        let mut stmt = conn.prepare("INSERT INTO valid_paths VALUES (?1)")?;
-        for path in valid_paths {
-            stmt.execute(params![path])?;
+impl std::fmt::Display for HashMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HashMismatch::Missing => write!(f, "image has no perceptual hash"),
+            HashMismatch::DifferentAlgorithms(a, b) => write!(
+                f,
+                "hashes made by {} and {} can't be compared, rescan with one algorithm",
+                a.as_str(),
+                b.as_str()
+            ),
         }
     }
-    let deleted = conn.execute(
-        "DELETE FROM images WHERE path NOT IN (SELECT path FROM valid_paths)",
-        [],
-    )?;
-    Ok(deleted)
 }
 
-fn system_time_to_unix(t: SystemTime) -> i64 {
-    t.duration_since(UNIX_EPOCH)
-        .map(|d| d.as_secs() as i64)
-        .unwrap_or(0)
+impl HashAlgo {
+    fn as_str(self) -> &'static str {
+        match self {
+            HashAlgo::Dhash => "dhash",
+            HashAlgo::Phash => "phash",
+            HashAlgo::DhashNormalized => "dhash_normalized",
+            HashAlgo::DhashDocument => "dhash_document",
+        }
+    }
+
+    // unknown/NULL values come from rows written before the column existed, which were all dHash
+    fn from_db(s: Option<&str>) -> Self {
+        match s {
+            Some("phash") => HashAlgo::Phash,
+            Some("dhash_normalized") => HashAlgo::DhashNormalized,
+            Some("dhash_document") => HashAlgo::DhashDocument,
+            _ => HashAlgo::Dhash,
+        }
+    }
 }
 
-fn compute_exif(bytes: &[u8]) -> Option<ExifData> {
-    let mut cursor = std::io::Cursor::new(bytes);
-    let exif = exif::Reader::new()
-        .read_from_container(&mut cursor)
-        .ok()?;
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImageInfo {
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    pub created_at: i64,
+    pub modified_at: i64,
+    #[serde(with = "hex_hash")]
+    pub phash: Option<u64>,
+    // average hash, a second opinion on phash matches. always the upright image
+    #[serde(default, with = "hex_hash")]
+    pub ahash: Option<u64>,
+    pub sha256: Option<String>,
+    pub exif: Option<ExifData>,
+    #[serde(with = "hex_hash_list")]
+    pub phash_rotations: Option<Vec<u64>>, // hashes of the 90/180/270° rotations, if computed
+    #[serde(default, with = "hex_hash")]
+    pub phash_mirror: Option<u64>, // hash of the horizontally flipped image, if computed
+    #[serde(default, with = "hex_hash_list")]
+    pub tile_hashes: Option<Vec<u64>>, // row-major hashes of overlapping tiles, if computed
+    #[serde(default)]
+    pub metadata: Option<ImageMetadata>,
+    #[serde(default)]
+    pub place: Option<String>, // reverse-geocoded GPS position, e.g. "Florence, Tuscany, IT"
+    #[serde(default)]
+    pub hash_algo: HashAlgo,
+    // false for rows from a metadata-only scan, sha256/phash are None and still owed
+    #[serde(default = "default_true")]
+    pub hashes_computed: bool,
+    #[serde(default)]
+    pub metadata_level: MetadataLevel,
+}
 
-    let get_str = |tag: Tag| -> Option<String> {
-        exif.get_field(tag, In::PRIMARY)
-            .map(|f: &exif::Field| f.display_value().to_string())
-    };
+fn default_true() -> bool {
+    true
+}
 
-    let get_u32 = |tag: Tag| -> Option<u32> {
-        exif.get_field(tag, In::PRIMARY).and_then(|f: &exif::Field| {
-            match &f.value {
-                Value::Long(v) => v.first().copied(),
-                Value::Short(v) => v.first().map(|&x| x as u32),
-                _ => None,
-            }
-        })
-    };
+// hashes are u64 in memory and INTEGER in the DB, but cross the IPC boundary as 16-char
+// hex — JS numbers can't hold 64 bits exactly, and hex is what gets displayed anyway
+fn phash_hex(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
 
-    // prefer DateTimeOriginal (when the photo was taken) over DateTime (when it was saved/edited)
-    let date = get_str(Tag::DateTimeOriginal)
-        .or_else(|| get_str(Tag::DateTime))
-        .and_then(|s: String| {
-            let s = s.trim_matches('"');
-            chrono::NaiveDateTime::parse_from_str(s, "%Y:%m:%d %H:%M:%S")
-                .ok()
-                .map(|dt| dt.and_utc().timestamp())
-        });
+mod hex_hash {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
 
-    Some(ExifData {
-        date,
-        make: get_str(Tag::Make).map(|s: String| s.trim_matches('"').to_string()),
-        model: get_str(Tag::Model).map(|s: String| s.trim_matches('"').to_string()),
-        width: get_u32(Tag::PixelXDimension),
-        height: get_u32(Tag::PixelYDimension),
-    })
-}
+    pub fn serialize<S: Serializer>(hash: &Option<u64>, s: S) -> Result<S::Ok, S::Error> {
+        match hash {
+            Some(h) => s.serialize_some(&super::phash_hex(*h)),
+            None => s.serialize_none(),
+        }
+    }
 
-fn compute_phash(bytes: &[u8]) -> Option<String> {
-    let img = image::load_from_memory(bytes)
-        .map_err(|e| eprintln!("image load failed: {e}"))
-        .ok()?;
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|h| super::parse_phash(&h).ok_or_else(|| D::Error::custom("invalid hash")))
+            .transpose()
+    }
+}
 
-    // dHash: resize to 9x8, compare adjacent pixels row-wise -> 64-bit hash
-    let small = img
-        .grayscale()
-        .resize_exact(9, 8, image::imageops::FilterType::Lanczos3);
-    let pixels: Vec<u8> = small.to_luma8().into_raw();
+mod hex_hash_list {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
 
-    let mut hash: u64 = 0;
-    for row in 0..8 {
-        for col in 0..8 {
-            let left = pixels[row * 9 + col] as u16;
-            let right = pixels[row * 9 + col + 1] as u16;
-            hash = (hash << 1) | if left > right { 1 } else { 0 };
+    pub fn serialize<S: Serializer>(hashes: &Option<Vec<u64>>, s: S) -> Result<S::Ok, S::Error> {
+        match hashes {
+            Some(v) => s.collect_seq(v.iter().map(|h| super::phash_hex(*h))),
+            None => s.serialize_none(),
         }
     }
 
-    Some(format!("{:016x}", hash))
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u64>>, D::Error> {
+        Option::<Vec<String>>::deserialize(d)?
+            .map(|v| {
+                v.iter()
+                    .map(|h| super::parse_phash(h).ok_or_else(|| D::Error::custom("invalid hash")))
+                    .collect()
+            })
+            .transpose()
+    }
 }
 
-fn compute_sha256(bytes: &[u8]) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(bytes);
-    hex::encode(hasher.finalize())
+// scanner knobs passed from the frontend. every field is optional on the JS side and
+// the defaults reproduce a plain recursive scan
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ScanOptions {
+    pub extensions: Option<Vec<String>>, // overrides get_extensions, case-insensitive, no dot
+    pub max_depth: Option<usize>,        // overrides `recursive` when set
+    pub follow_links: bool,
+    pub include_hidden: bool, // dot-files, NAS/OS system folders and (on Windows) hidden files
+    pub rotation_invariant: bool, // also hash 90/180/270° rotations — 4x the hashing work
+    pub crop_resistant: bool, // also hash tiles for find_cropped_duplicates — 10x the work
+    pub mirror_invariant: bool, // also hash the flipped image, for mirrored selfies and scans
+    pub hash_algo: HashAlgo,
+    pub content_type: ContentType,
+    pub compute_hashes: bool, // false skips SHA-256 and pHash for a metadata-only scan
+    pub metadata_level: MetadataLevel, // less than full skips EXIF parsing, for speed
+    // glob patterns matched against the path relative to the scanned folder, e.g.
+    // "2023-edits" or "**/exports". case-insensitive on Windows, case-sensitive elsewhere
+    pub exclude: Vec<String>,
+    // for network mounts that can hang: a limit per file and for the whole scan. a file
+    // that hits its limit is reported with ScanErrorKind::Timeout and handled per
+    // timeout_policy, hitting the deadline stops the scan with the partial results
+    pub file_timeout_secs: Option<u64>,
+    pub deadline_secs: Option<u64>,
+    pub timeout_policy: TimeoutPolicy,
+    // hashing threads. None uses one per core, which suits SSDs. on spinning disks and
+    // NAS shares parallel reads make the heads seek back and forth, and 1-2 threads
+    // usually finish sooner
+    pub threads: Option<usize>,
+    // images whose header claims more pixels than this are reported instead of decoded,
+    // so a decompression bomb can't take gigabytes of memory
+    pub max_pixels: u64,
+    // icons, sprites and web thumbnails under either limit are skipped (and reported with
+    // ScanErrorKind::TooSmall) before anything is hashed
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    // also list the images inside .zip archives, as archive.zip!/dir/photo.jpg. they can
+    // be hashed and compared but not opened or deleted from here
+    pub scan_archives: bool,
 }
 
-fn process_image_file(file_path: &Path) -> Option<ImageInfo> {
-    let meta = fs::metadata(file_path).ok()?;
-    let size = meta.len();
-    let mtime = system_time_to_unix(meta.modified().ok()?);
-    let created_at = system_time_to_unix(meta.created().unwrap_or(SystemTime::UNIX_EPOCH));
-    let path_str = file_path.to_string_lossy().to_string();
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            extensions: None,
+            max_depth: None,
+            follow_links: false,
+            include_hidden: false,
+            rotation_invariant: false,
+            crop_resistant: false,
+            mirror_invariant: false,
+            hash_algo: HashAlgo::Dhash,
+            content_type: ContentType::Photo,
+            compute_hashes: true,
+            metadata_level: MetadataLevel::Full,
+            exclude: Vec::new(),
+            file_timeout_secs: None,
+            deadline_secs: None,
+            timeout_policy: TimeoutPolicy::Skip,
+            threads: None,
+            max_pixels: MAX_IMAGE_PIXELS,
+            min_width: None,
+            min_height: None,
+            scan_archives: false,
+        }
+    }
+}
 
-    // cache hit — skip all processing
-    if let Some(cached) = cache_get(&path_str, mtime, size) {
-        return Some(cached);
+impl ScanOptions {
+    // the algorithm this scan hashes with
+    fn scan_hash_algo(&self) -> HashAlgo {
+        match self.content_type {
+            ContentType::Photo => self.hash_algo,
+            ContentType::Document => HashAlgo::DhashDocument,
+        }
     }
+}
 
-    // cache miss — read and process the file
-    let bytes = fs::read(file_path).ok()?;
-    let mut exif = compute_exif(&bytes);
-    let phash = compute_phash(&bytes);
-    let sha1 = Some(compute_sha256(&bytes));
+// filters for query_images, all optional and combined with AND
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ImageQuery {
+    pub folder: Option<String>,
+    pub formats: Option<Vec<String>>, // as stored by image_format, e.g. "jpeg", "png"
+    pub min_pixels: Option<u64>,
+    pub max_pixels: Option<u64>,
+    pub orientation: Option<String>, // "portrait", "landscape" or "square"
+    pub favorite: Option<bool>,
+    pub place: Option<String>, // case-insensitive substring of the geocoded place
+}
 
-    // fallback: if EXIF didn't provide dimensions (common for PNG/WebP),
-    // read from image headers. image::image_dimensions() only reads the file
-    // header so it's very cheap — no full decode.
-    let needs_dims = exif.as_ref().map_or(true, |e| e.width.is_none());
-    if needs_dims {
-        if let Ok((w, h)) = image::image_dimensions(file_path) {
-            match exif {
-                Some(ref mut e) => {
-                    e.width = Some(w);
-                    e.height = Some(h);
-                }
-                None => {
-                    exif = Some(ExifData {
-                        date: None,
-                        make: None,
-                        model: None,
-                        width: Some(w),
-                        height: Some(h),
-                    });
-                }
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    Name,
+    Size,
+    CreatedAt,
+    ModifiedAt,
+    Date, // EXIF capture date, falling back to created_at like image_date
+}
+
+impl SortBy {
+    fn sql(self) -> &'static str {
+        match self {
+            SortBy::Name => "name",
+            SortBy::Size => "size",
+            SortBy::CreatedAt => "created_at",
+            SortBy::ModifiedAt => "modified_at",
+            // milliseconds, so burst shots from the same second keep their order
+            SortBy::Date => {
+                "COALESCE(json_extract(exif_json, '$.date_ms'),
+                          json_extract(exif_json, '$.date') * 1000, created_at * 1000)"
             }
         }
     }
+}
 
-    let info = ImageInfo {
-        path: path_str,
-        name: file_path.file_name()?.to_string_lossy().to_string(),
-        size,
-        created_at,
-        modified_at: mtime,
-        phash,
-        sha1,
-        exif,
-    };
-
-    if let Err(e) = cache_set(&info) {
-        eprintln!("Cache write error for {:?}: {}", file_path, e);
-    }
+#[derive(Debug, Serialize, Clone)]
+pub struct FormatStats {
+    pub format: Option<String>, // lowercase extension, None for files without one
+    pub count: u64,
+    pub bytes: u64,
+}
 
-    Some(info)
+// overview of the cached library for a dashboard
+#[derive(Debug, Serialize, Clone)]
+pub struct FolderStats {
+    pub total_images: u64,
+    pub total_bytes: u64,
+    pub by_format: Vec<FormatStats>, // largest count first
+    pub with_exif_date: u64,
+    pub without_exif_date: u64,
+    // capture date range, falling back to created_at like SortBy::Date
+    pub earliest: Option<i64>,
+    pub latest: Option<i64>,
 }
 
-// hamming distance between two hex-encoded hashes
-fn phash_distance(a: &str, b: &str) -> u32 {
-    let a_bytes = hex::decode(a).unwrap_or_default();
-    let b_bytes = hex::decode(b).unwrap_or_default();
-    if a_bytes.len() != b_bytes.len() {
-        return u32::MAX;
-    }
-    a_bytes
-        .iter()
-        .zip(b_bytes.iter())
-        .map(|(x, y)| (x ^ y).count_ones())
-        .sum()
+// how the cached rows under a folder compare with the files on disk right now
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct LibraryVerification {
+    pub unchanged: usize,
+    pub stale: Vec<String>, // mtime or size differs from the cached row
+    pub missing: Vec<String>,
+    pub rehashed: usize, // stale files reprocessed, when verify_library was asked to
 }
 
-// tauri commands
+#[derive(Debug, Serialize, Clone)]
+pub struct ImagePage {
+    pub images: Vec<ImageInfo>,
+    pub total: u64, // rows matching the folder filter, across all pages
+}
 
-#[tauri::command]
-fn scan_folder(
-    folder_path: String,
-    recursive: bool,
-    app: tauri::AppHandle,
-) -> Result<Vec<ImageInfo>, String> {
-    println!("Scanning: {} (recursive: {})", folder_path, recursive);
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Rating {
+    pub rating: i32, // 0-5, 0 = unrated
+    pub favorite: bool,
+}
 
-    let walker = WalkDir::new(&folder_path);
-    let walker = if recursive {
-        walker
-    } else {
-        walker.max_depth(1)
-    };
+#[derive(Debug, Serialize, Clone)]
+pub struct MovedFile {
+    pub from: String,
+    pub to: String,
+}
 
-    let paths: Vec<std::path::PathBuf> = walker
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| {
-            e.path()
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .map(|ext| IMAGE_EXTS.contains(&ext.to_lowercase().as_str()))
-                .unwrap_or(false)
-        })
-        .map(|e| e.path().to_path_buf())
-        .collect();
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum FileOperation {
+    Move { files: Vec<MovedFile> },
+}
 
-    let total = paths.len();
-    println!("Found {} image files", total);
+#[derive(Debug, Serialize, Clone)]
+pub struct UndoResult {
+    pub operation: FileOperation,
+    pub restored: usize,
+    pub errors: Vec<String>,
+}
 
-    // emit initial event so the frontend knows the total right away
-    let _ = app.emit("scan-progress", ScanProgress { current: 0, total });
+// one file's planned (or completed) move in organize_by_date
+#[derive(Debug, Serialize, Clone)]
+pub struct PlannedMove {
+    pub from: String,
+    pub to: String,
+    pub moved: bool, // always false for a dry run
+    pub error: Option<String>,
+}
 
-    let counter = Arc::new(AtomicUsize::new(0));
+#[derive(Debug, Serialize, Clone)]
+pub struct ScoredImage {
+    pub image: ImageInfo,
+    pub closest_distance: u32,
+}
 
-    let images: Vec<ImageInfo> = paths
-        .par_iter()
-        .filter_map(|p| {
-            let result = process_image_file(p);
-            let current = counter.fetch_add(1, Ordering::Relaxed) + 1;
-            // throttle events: emit every 10 files and on the last one to avoid flooding the frontend
-            if current % 10 == 0 || current == total {
-                let _ = app.emit("scan-progress", ScanProgress { current, total });
-            }
-            result
-        })
-        .collect();
+#[derive(Debug, Serialize, Clone)]
+pub struct GroupSummary {
+    pub count: usize,
+    pub total_bytes: u64,
+    pub keep_path: String,      // recommended keeper
+    pub reclaimable_bytes: u64, // freed if everything except the keeper is deleted
+}
 
-    // clean up cache rows for files that have been deleted since the last scan
-    let valid_paths: Vec<String> = images.iter().map(|i| i.path.clone()).collect();
-    if let Err(e) = cache_prune(&valid_paths) {
-        eprintln!("Cache prune error: {}", e);
-    }
+// how auto_select_keepers picks the one image to keep in each group
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub enum KeepRule {
+    KeepLargest,
+    KeepHighestResolution,
+    KeepNewest,
+    KeepOldest,
+    KeepShortestPath,
+}
 
-    println!("Scan complete: {} images processed", images.len());
-    Ok(images)
+#[derive(Debug, Serialize, Clone)]
+pub struct KeeperSelection {
+    pub keep: String,
+    pub delete: Vec<String>,
 }
 
-#[tauri::command]
-fn find_similar_duplicates(images: Vec<ImageInfo>) -> Vec<Vec<ImageInfo>> {
-    let with_hash: Vec<&ImageInfo> = images.iter().filter(|i| i.phash.is_some()).collect();
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchKind {
+    Exact,
+    Similar,
+}
 
-    let mut groups: Vec<Vec<ImageInfo>> = Vec::new();
-    let mut processed = vec![false; with_hash.len()];
+// a file in compare_two_folders' folder_a that also exists in folder_b
+#[derive(Debug, Serialize, Clone)]
+pub struct FolderMatch {
+    pub a: ImageInfo,
+    pub b: ImageInfo,
+    pub kind: MatchKind,
+    pub distance: u32, // 0 for exact matches
+}
 
-    for i in 0..with_hash.len() {
-        if processed[i] {
-            continue;
-        }
+// payload of the "library-changed" event sent by the folder watcher
+#[derive(Debug, Serialize, Clone)]
+pub struct LibraryChange {
+    pub updated: Vec<ImageInfo>,
+    pub removed: Vec<String>,
+}
 
-        let mut group = vec![with_hash[i].clone()];
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageKind {
+    Photo,
+    Screenshot,
+    Unknown, // no camera metadata but nothing screenshot-like either, e.g. saved memes
+}
 
-        for j in (i + 1)..with_hash.len() {
-            if processed[j] {
-                continue;
-            }
-            let hash_j = with_hash[j].phash.as_ref().unwrap();
-            // compare against any existing group member, not just the seed image
-            let is_similar = group.iter().any(|g| {
-                phash_distance(g.phash.as_ref().unwrap(), hash_j) <= PHASH_THRESHOLD
-            });
-            if is_similar {
-                group.push(with_hash[j].clone());
-                processed[j] = true;
-            }
-        }
+#[derive(Debug, Serialize, Clone)]
+pub struct ImageClass {
+    pub path: String,
+    pub kind: ImageKind,
+}
 
-        if group.len() > 1 {
-            groups.push(group);
-            processed[i] = true;
-        }
-    }
+// why find_broken_images flagged a file
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrokenReason {
+    Empty,
+    TooSmall,         // under MIN_IMAGE_BYTES
+    UnreadableHeader, // no dimensions could be read, e.g. cut off before the image header
+}
 
-    groups
+#[derive(Debug, Serialize, Clone)]
+pub struct BrokenImage {
+    pub path: String,
+    pub size: u64,
+    pub reason: BrokenReason,
 }
 
-#[tauri::command]
-fn find_exact_duplicates(images: Vec<ImageInfo>) -> Vec<Vec<ImageInfo>> {
-    // group by sha256 hash, anything with more than one entry is a duplicate
-    let mut map: std::collections::HashMap<String, Vec<ImageInfo>> =
-        std::collections::HashMap::new();
+#[derive(Debug, Serialize, Clone)]
+pub struct BlurScore {
+    pub path: String,
+    pub sharpness: Option<f64>, // variance of the Laplacian, None if the image couldn't be decoded
+    pub blurry: bool,
+    pub error: Option<String>,
+}
 
-    for img in images {
-        if let Some(ref sha1) = img.sha1 {
-            map.entry(sha1.clone()).or_default().push(img);
-        }
-    }
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ExposureStats {
+    pub path: String,
+    // all 0..1 and None if the image couldn't be decoded
+    pub mean_luminance: Option<f64>,
+    pub clipped_black: Option<f64>, // share of pixels at or below CLIPPED_BLACK_LUMA
+    pub clipped_white: Option<f64>, // share of pixels at or above CLIPPED_WHITE_LUMA
+    pub too_dark: bool,
+    pub blown_out: bool,
+    pub error: Option<String>,
+}
 
-    map.into_values().filter(|g| g.len() > 1).collect()
+// overall color of an image, for browsing by palette rather than by content
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ColorSignature {
+    pub average: [u8; 3],
+    pub histogram: Vec<f32>, // COLOR_BINS^3 RGB bins, each the fraction of pixels in it
 }
 
-#[tauri::command]
-fn delete_images(paths: Vec<String>) -> Vec<serde_json::Value> {
+#[derive(Debug, Serialize, Clone)]
+pub struct ColorResult {
+    pub path: String,
+    pub signature: Option<ColorSignature>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct QuarantinedFile {
+    pub path: String,
+    pub reason: String,
+    pub quarantined_at: i64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RawJpegPair {
+    pub raw: ImageInfo,
+    pub jpeg: ImageInfo,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CacheStats {
+    pub count: u64,
+    pub image_bytes: u64, // total size of the cached files on disk
+    pub db_bytes: u64,    // the DB file plus its WAL
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DbMaintenance {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ThumbnailCacheStats {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+pub enum ScanErrorKind {
+    IoError,
+    DecodeError,
+    MetadataError,
+    SymlinkLoop, // a warning: the linked folder was already walked and got skipped
+    Timeout,
+    TooSmall, // a warning: below min_width/min_height, so left out of the library
+}
+
+// a file that couldn't be (fully) processed, emitted as a "scan-error" event
+#[derive(Debug, Serialize, Clone)]
+pub struct ScanError {
+    pub path: String,
+    pub kind: ScanErrorKind,
+    pub reason: String,
+}
+
+impl ScanError {
+    fn new(path: &Path, kind: ScanErrorKind, reason: impl ToString) -> Self {
+        ScanError {
+            path: path.to_string_lossy().to_string(),
+            kind,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+// result of processing one file. an undecodable image is still listed (without a hash),
+// so its decode failure is reported alongside it rather than instead of it
+struct ProcessedImage {
+    info: ImageInfo,
+    from_cache: bool,
+    decode_error: Option<ScanError>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ScanPhase {
+    Discovering, // walking the folder tree, total isn't known yet
+    Hashing,
+    Finalizing, // writing the cache and pruning deleted files
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct ScanProgress {
+    phase: ScanPhase,
+    current: usize,
+    total: usize,
+    // file sizes of the same, so a bar weighted by size doesn't jump on big RAWs
+    bytes_done: u64,
+    bytes_total: u64,
+}
+
+// emitted mid-scan each time another file turns up with an already seen SHA-256.
+// `paths` is every file with that hash so far, so the latest event for a hash supersedes
+// the earlier ones
+#[derive(Debug, Serialize, Clone)]
+struct DuplicateFound {
+    sha256: String,
+    paths: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct ThumbnailProgress {
+    current: usize,
+    total: usize,
+    done: bool, // the last event, sent once everything is generated or it was cancelled
+}
+
+// ordered schema migrations. PRAGMA user_version holds how many have been applied, so
+// new columns/tables go in as a new entry at the end — never edit an existing one
+type Migration = fn(&rusqlite::Transaction) -> SqlResult<()>;
+
+const MIGRATIONS: &[Migration] = &[
+    migrate_initial_schema,
+    migrate_sha1_to_sha256,
+    migrate_phash_rotations,
+    migrate_hash_algo,
+    migrate_thumbnails,
+    migrate_phash_integer,
+    migrate_hashes_computed,
+    migrate_query_columns,
+    migrate_tags,
+    migrate_ratings,
+    migrate_color_signatures,
+    migrate_tile_hashes,
+    migrate_quarantine,
+    migrate_metadata,
+    migrate_search_index,
+    migrate_hash_generation,
+    migrate_ahash,
+    migrate_phash_mirror,
+    migrate_settings,
+    migrate_places,
+    migrate_metadata_level,
+    migrate_deep_rehash,
+];
+
+// v0: the schema from before versioning existed. IF NOT EXISTS because caches created
+// back then already have it with user_version = 0
+fn migrate_initial_schema(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS images (
+            path        TEXT PRIMARY KEY,
+            name        TEXT NOT NULL,
+            size        INTEGER NOT NULL,
+            created_at  INTEGER NOT NULL,
+            modified_at INTEGER NOT NULL,
+            phash       TEXT,
+            sha1        TEXT,
+            exif_json   TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_phash ON images(phash);",
+    )?;
+    // an unversioned cache whose column was already renamed has nothing to index
+    if images_has_sha1(tx)? {
+        tx.execute_batch("CREATE INDEX IF NOT EXISTS idx_sha1 ON images(sha1);")?;
+    }
+    Ok(())
+}
+
+fn images_has_sha1(tx: &rusqlite::Transaction) -> SqlResult<bool> {
+    tx.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('images') WHERE name = 'sha1'",
+        [],
+        |row| row.get(0),
+    )
+}
+
+// v1: the sha1 column always held a SHA-256. RENAME COLUMN keeps the data; the check is
+// there because some unversioned caches were already renamed (and have no sha1 index)
+fn migrate_sha1_to_sha256(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    if images_has_sha1(tx)? {
+        tx.execute_batch("ALTER TABLE images RENAME COLUMN sha1 TO sha256;")?;
+    }
+    tx.execute_batch(
+        "DROP INDEX IF EXISTS idx_sha1;
+         CREATE INDEX IF NOT EXISTS idx_sha256 ON images(sha256);",
+    )
+}
+
+// v2: JSON list of rotated-image hashes for rotation-invariant matching
+fn migrate_phash_rotations(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch("ALTER TABLE images ADD COLUMN phash_rotations TEXT;")
+}
+
+// v3: which algorithm produced phash, existing rows are all dHash
+fn migrate_hash_algo(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch("ALTER TABLE images ADD COLUMN hash_algo TEXT NOT NULL DEFAULT 'dhash';")
+}
+
+// v4: encoded JPEG thumbnails, one per (path, size). mtime invalidates like the images table
+fn migrate_thumbnails(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS thumbnails (
+            path        TEXT NOT NULL,
+            max_edge    INTEGER NOT NULL,
+            modified_at INTEGER NOT NULL,
+            data        BLOB NOT NULL,
+            PRIMARY KEY (path, max_edge)
+        );",
+    )
+}
+
+// v5: phash as an INTEGER (the u64 bits reinterpreted as i64) instead of hex text, and
+// phash_rotations as a JSON list of integers. the column type can't be altered in place,
+// so the hex column is renamed aside, converted row by row, then dropped
+fn migrate_phash_integer(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "DROP INDEX IF EXISTS idx_phash;
+         ALTER TABLE images RENAME COLUMN phash TO phash_hex;
+         ALTER TABLE images ADD COLUMN phash INTEGER;",
+    )?;
+    {
+        let mut select = tx.prepare(
+            "SELECT path, phash_hex, phash_rotations FROM images
+             WHERE phash_hex IS NOT NULL OR phash_rotations IS NOT NULL",
+        )?;
+        let rows = select
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        let mut update =
+            tx.prepare("UPDATE images SET phash = ?2, phash_rotations = ?3 WHERE path = ?1")?;
+        for (path, hex, rotations) in rows {
+            let phash = hex.as_deref().and_then(parse_phash).map(|h| h as i64);
+            let rotations = rotations
+                .and_then(|j| serde_json::from_str::<Vec<String>>(&j).ok())
+                .map(|r| {
+                    r.iter()
+                        .filter_map(|h| parse_phash(h))
+                        .collect::<Vec<u64>>()
+                })
+                .and_then(|r| serde_json::to_string(&r).ok());
+            update.execute(params![path, phash, rotations])?;
+        }
+    }
+    tx.execute_batch(
+        "ALTER TABLE images DROP COLUMN phash_hex;
+         CREATE INDEX IF NOT EXISTS idx_phash ON images(phash);",
+    )
+}
+
+// v6: metadata-only scans leave the hashes empty, existing rows all have them
+fn migrate_hashes_computed(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch("ALTER TABLE images ADD COLUMN hashes_computed INTEGER NOT NULL DEFAULT 1;")
+}
+
+// v7: columns derived from the path and EXIF so query_images can filter in SQL. they're
+// filled in by write_images from then on; existing rows are backfilled here
+fn migrate_query_columns(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE images ADD COLUMN format TEXT;
+         ALTER TABLE images ADD COLUMN pixels INTEGER;
+         ALTER TABLE images ADD COLUMN orientation TEXT;
+         CREATE INDEX IF NOT EXISTS idx_format ON images(format);
+         CREATE INDEX IF NOT EXISTS idx_pixels ON images(pixels);
+         CREATE INDEX IF NOT EXISTS idx_orientation ON images(orientation);",
+    )?;
+    let mut select = tx.prepare("SELECT path, exif_json FROM images")?;
+    let rows = select
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?
+        .collect::<SqlResult<Vec<_>>>()?;
+
+    let mut update =
+        tx.prepare("UPDATE images SET format = ?2, pixels = ?3, orientation = ?4 WHERE path = ?1")?;
+    for (path, exif_json) in rows {
+        let exif: Option<ExifData> = exif_json.and_then(|j| serde_json::from_str(&j).ok());
+        update.execute(params![
+            path,
+            image_format(&path),
+            exif_pixels(exif.as_ref()),
+            image_shape(exif.as_ref()),
+        ])?;
+    }
+    Ok(())
+}
+
+// v8: user tags. keyed on path rather than a foreign key into images so they survive
+// the INSERT OR REPLACE a rescan does; pruned alongside the image rows instead
+fn migrate_tags(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tags (
+            id   INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE
+        );
+        CREATE TABLE IF NOT EXISTS image_tags (
+            path   TEXT NOT NULL,
+            tag_id INTEGER NOT NULL REFERENCES tags(id),
+            PRIMARY KEY (path, tag_id)
+        );
+        CREATE INDEX IF NOT EXISTS idx_image_tags_tag ON image_tags(tag_id);",
+    )
+}
+
+// v9: star ratings and favorites, keyed on path for the same reason as image_tags
+fn migrate_ratings(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ratings (
+            path     TEXT PRIMARY KEY,
+            rating   INTEGER NOT NULL DEFAULT 0,
+            favorite INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX IF NOT EXISTS idx_ratings_favorite ON ratings(favorite);",
+    )
+}
+
+// v10: color signatures as JSON, invalidated by mtime like thumbnails
+fn migrate_color_signatures(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS color_signatures (
+            path        TEXT PRIMARY KEY,
+            modified_at INTEGER NOT NULL,
+            signature   TEXT NOT NULL
+        );",
+    )
+}
+
+// v11: crop-resistant tile hashes, a JSON list of integers like phash_rotations
+fn migrate_tile_hashes(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch("ALTER TABLE images ADD COLUMN tile_hashes TEXT;")
+}
+
+// v12: files that failed to decode and may be corrupt
+fn migrate_quarantine(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS quarantine (
+            path           TEXT PRIMARY KEY,
+            reason         TEXT NOT NULL,
+            quarantined_at INTEGER NOT NULL
+        );",
+    )
+}
+
+// v13: XMP/IPTC captions and keywords as JSON. rows from before stay NULL until rescanned
+fn migrate_metadata(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch("ALTER TABLE images ADD COLUMN metadata_json TEXT;")
+}
+
+// what the search index holds for an images row, as (path, name, keywords, caption)
+// expressions over `{row}`. shared by the sync triggers and rebuild_search_index
+fn search_index_values(row: &str) -> String {
+    format!(
+        "{row}.path, {row}.name,
+         (SELECT group_concat(value, ' ') FROM json_each({row}.metadata_json, '$.keywords')),
+         trim(coalesce(json_extract({row}.metadata_json, '$.title'), '') || ' ' ||
+              coalesce(json_extract({row}.metadata_json, '$.description'), ''))"
+    )
+}
+
+// v14: FTS5 index over path, name, keywords and captions, keyed by the images rowid and
+// kept in sync by triggers. INSERT OR REPLACE doesn't fire delete triggers, so the old
+// entry is dropped before the insert instead
+fn migrate_search_index(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    let new_values = search_index_values("new");
+    tx.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS images_fts USING fts5(
+            path, name, keywords, caption,
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+        CREATE TRIGGER IF NOT EXISTS images_fts_before_insert BEFORE INSERT ON images BEGIN
+            DELETE FROM images_fts
+            WHERE rowid = (SELECT rowid FROM images WHERE path = new.path);
+        END;
+        CREATE TRIGGER IF NOT EXISTS images_fts_insert AFTER INSERT ON images BEGIN
+            INSERT INTO images_fts (rowid, path, name, keywords, caption)
+            VALUES (new.rowid, {new_values});
+        END;
+        CREATE TRIGGER IF NOT EXISTS images_fts_delete AFTER DELETE ON images BEGIN
+            DELETE FROM images_fts WHERE rowid = old.rowid;
+        END;
+        CREATE TRIGGER IF NOT EXISTS images_fts_update
+        AFTER UPDATE OF path, name, metadata_json ON images BEGIN
+            DELETE FROM images_fts WHERE rowid = old.rowid;
+            INSERT INTO images_fts (rowid, path, name, keywords, caption)
+            VALUES (new.rowid, {new_values});
+        END;"
+    ))?;
+    rebuild_search_index(tx)
+}
+
+// refills the search index from scratch. needed after VACUUM too, which may renumber
+// the rowids it's keyed on
+fn rebuild_search_index(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(&format!(
+        "DELETE FROM images_fts;
+         INSERT INTO images_fts (rowid, path, name, keywords, caption)
+         SELECT images.rowid, {} FROM images;",
+        search_index_values("images")
+    ))
+}
+
+// v15: a counter bumped by every change to the cached set of hashes, so the persisted
+// similarity index can tell it's stale however the rows were changed
+fn migrate_hash_generation(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS hash_generation (
+            id    INTEGER PRIMARY KEY CHECK (id = 0),
+            value INTEGER NOT NULL
+        );
+        INSERT OR IGNORE INTO hash_generation VALUES (0, 0);
+        CREATE TRIGGER IF NOT EXISTS hash_generation_insert AFTER INSERT ON images BEGIN
+            UPDATE hash_generation SET value = value + 1;
+        END;
+        CREATE TRIGGER IF NOT EXISTS hash_generation_delete AFTER DELETE ON images BEGIN
+            UPDATE hash_generation SET value = value + 1;
+        END;
+        CREATE TRIGGER IF NOT EXISTS hash_generation_update
+        AFTER UPDATE OF path, phash, phash_rotations, hash_algo ON images BEGIN
+            UPDATE hash_generation SET value = value + 1;
+        END;",
+    )
+}
+
+// v16: average hash next to phash. older rows are rehashed on their next scan
+fn migrate_ahash(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch("ALTER TABLE images ADD COLUMN ahash INTEGER;")
+}
+
+// v17: hash of the horizontally mirrored image, for mirror_invariant scans
+fn migrate_phash_mirror(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch("ALTER TABLE images ADD COLUMN phash_mirror INTEGER;")
+}
+
+// v18: app preferences as key -> JSON value
+fn migrate_settings(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key   TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
+    )
+}
+
+// v19: geocoded place per image, plus a lookup cache keyed on coordinates rounded by
+// place_key so nearby photos share one lookup
+fn migrate_places(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "ALTER TABLE images ADD COLUMN place TEXT;
+         CREATE INDEX IF NOT EXISTS idx_images_place ON images(place);
+         CREATE TABLE IF NOT EXISTS places (
+            lat_key    INTEGER NOT NULL,
+            lon_key    INTEGER NOT NULL,
+            place_json TEXT NOT NULL,
+            PRIMARY KEY (lat_key, lon_key)
+         );",
+    )
+}
+
+// v20: how much metadata each row was scanned with, NULL meaning full
+fn migrate_metadata_level(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch("ALTER TABLE images ADD COLUMN metadata_level TEXT;")
+}
+
+// v21: 16-bit images are hashed at full depth now. only PNG and TIFF can be 16-bit, and
+// dropping their aHash is what makes the next scan rehash a row (8-bit ones come out
+// the same as before)
+fn migrate_deep_rehash(tx: &rusqlite::Transaction) -> SqlResult<()> {
+    tx.execute_batch(
+        "UPDATE images SET ahash = NULL
+         WHERE format IN ('png', 'tiff') AND phash IS NOT NULL;",
+    )
+}
+
+// applies every migration past the DB's user_version, each in its own transaction
+// together with the version bump so a failure can't leave a half-migrated schema
+fn run_migrations(conn: &mut Connection) -> SqlResult<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", (i + 1) as i64)?;
+        tx.commit()?;
+        println!("DB migrated to schema version {}", i + 1);
+    }
+    Ok(())
+}
+
+fn db_path(app_data_dir: &str) -> String {
+    format!("{}/image_cache.db", app_data_dir)
+}
+
+fn init_db(app_data_dir: &str) -> SqlResult<Connection> {
+    let db_path = db_path(app_data_dir);
+    let mut conn = Connection::open(&db_path)?;
+
+    // WAL mode gives much better concurrent read performance
+    conn.execute_batch("PRAGMA journal_mode=WAL;")?;
+    conn.execute_batch("PRAGMA synchronous=NORMAL;")?;
+    run_migrations(&mut conn)?;
+
+    println!("DB initialized at: {}", db_path);
+    Ok(conn)
+}
+
+// must run after init_db so the file and schema exist before opening read-only
+fn init_read_pool(app_data_dir: &str) -> Result<r2d2::Pool<SqliteConnectionManager>, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(db_path(app_data_dir)).with_flags(
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI,
+    );
+    // one per rayon worker plus a couple for commands running alongside a scan
+    r2d2::Pool::builder()
+        .max_size(rayon::current_num_threads() as u32 + 2)
+        .build(manager)
+}
+
+const IMAGE_COLUMNS: &str = "path, name, size, created_at, modified_at, phash, sha256, \
+                             exif_json, phash_rotations, hash_algo, hashes_computed, tile_hashes, \
+                             metadata_json, ahash, phash_mirror, place, metadata_level";
+
+// maps a row selected with IMAGE_COLUMNS back into an ImageInfo
+fn row_to_image(row: &rusqlite::Row) -> SqlResult<ImageInfo> {
+    let exif_json: Option<String> = row.get(7)?;
+    let exif = exif_json.and_then(|j| serde_json::from_str(&j).ok());
+    let rotations_json: Option<String> = row.get(8)?;
+    let hash_algo: Option<String> = row.get(9)?;
+    let tiles_json: Option<String> = row.get(11)?;
+    let metadata_json: Option<String> = row.get(12)?;
+    let metadata_level: Option<String> = row.get(16)?;
+    Ok(ImageInfo {
+        path: row.get(0)?,
+        name: row.get(1)?,
+        size: row.get::<_, i64>(2)? as u64,
+        created_at: row.get(3)?,
+        modified_at: row.get(4)?,
+        phash: row.get::<_, Option<i64>>(5)?.map(|h| h as u64),
+        ahash: row.get::<_, Option<i64>>(13)?.map(|h| h as u64),
+        phash_mirror: row.get::<_, Option<i64>>(14)?.map(|h| h as u64),
+        sha256: row.get(6)?,
+        exif,
+        phash_rotations: rotations_json.and_then(|j| serde_json::from_str(&j).ok()),
+        hash_algo: HashAlgo::from_db(hash_algo.as_deref()),
+        hashes_computed: row.get(10)?,
+        tile_hashes: tiles_json.and_then(|j| serde_json::from_str(&j).ok()),
+        metadata: metadata_json.and_then(|j| serde_json::from_str(&j).ok()),
+        place: row.get(15)?,
+        metadata_level: MetadataLevel::from_db(metadata_level.as_deref()),
+    })
+}
+
+// cache lookup — validates against mtime and size so stale entries don't get returned
+fn cache_get(path: &str, mtime: i64, size: u64) -> Option<ImageInfo> {
+    let conn = read_db().ok()?;
+    conn.query_row(
+        &format!(
+            "SELECT {IMAGE_COLUMNS} FROM images
+             WHERE path = ?1 AND modified_at = ?2 AND size = ?3"
+        ),
+        params![path, mtime, size as i64],
+        row_to_image,
+    )
+    .ok()
+}
+
+// folder path with exactly one trailing separator, so "/photos" doesn't match "/photos2/x.jpg"
+fn folder_prefix(folder: &str) -> String {
+    let trimmed = folder.trim_end_matches(['/', '\\']);
+    format!("{}{}", trimmed, std::path::MAIN_SEPARATOR)
+}
+
+// every cached row, optionally limited to paths under `folder`
+fn cache_list(conn: &Connection, folder: Option<&str>) -> SqlResult<Vec<ImageInfo>> {
+    match folder {
+        Some(folder) => {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {IMAGE_COLUMNS} FROM images
+                 WHERE substr(path, 1, length(?1)) = ?1 ORDER BY path"
+            ))?;
+            let rows = stmt.query_map(params![folder_prefix(folder)], row_to_image)?;
+            rows.collect()
+        }
+        None => {
+            let mut stmt =
+                conn.prepare(&format!("SELECT {IMAGE_COLUMNS} FROM images ORDER BY path"))?;
+            let rows = stmt.query_map([], row_to_image)?;
+            rows.collect()
+        }
+    }
+}
+
+// queues a row for the writer thread, which commits it with the next batch
+fn cache_queue(info: ImageInfo) {
+    let writer = WRITER.get().expect("DB writer not initialized");
+    if writer.send(WriterMsg::Image(Box::new(info))).is_err() {
+        eprintln!("DB writer has stopped, row dropped");
+    }
+}
+
+// blocks until everything queued so far is committed
+fn cache_flush() -> Result<(), String> {
+    let writer = WRITER.get().expect("DB writer not initialized");
+    let (reply, done) = std::sync::mpsc::channel();
+    writer
+        .send(WriterMsg::Flush(reply))
+        .map_err(|_| "DB writer has stopped".to_string())?;
+    done.recv()
+        .map_err(|_| "DB writer has stopped".to_string())?
+}
+
+fn cache_set_batch(images: &[ImageInfo]) -> Result<(), String> {
+    for img in images {
+        cache_queue(img.clone());
+    }
+    cache_flush()
+}
+
+// owns the scan write connection. rows are committed CACHE_BATCH_SIZE at a time — one
+// INSERT per file with its own implicit transaction is what made cold scans crawl
+fn run_writer(mut conn: Connection, rx: std::sync::mpsc::Receiver<WriterMsg>) {
+    let mut pending: Vec<ImageInfo> = Vec::with_capacity(CACHE_BATCH_SIZE);
+    let mut error: Option<String> = None;
+    let commit =
+        |conn: &mut Connection, pending: &mut Vec<ImageInfo>, error: &mut Option<String>| {
+            if let Err(e) = write_images(conn, pending) {
+                eprintln!("Cache write error: {}", e);
+                error.get_or_insert(e.to_string());
+            }
+            pending.clear();
+        };
+    for msg in rx {
+        match msg {
+            WriterMsg::Image(info) => {
+                pending.push(*info);
+                if pending.len() >= CACHE_BATCH_SIZE {
+                    commit(&mut conn, &mut pending, &mut error);
+                }
+            }
+            WriterMsg::Quarantine { path, reason } => {
+                let result = conn.execute(
+                    "INSERT OR REPLACE INTO quarantine (path, reason, quarantined_at)
+                     VALUES (?1, ?2, ?3)",
+                    params![path, reason, system_time_to_unix(SystemTime::now())],
+                );
+                if let Err(e) = result {
+                    eprintln!("Quarantine write error for {}: {}", path, e);
+                }
+            }
+            WriterMsg::Flush(reply) => {
+                commit(&mut conn, &mut pending, &mut error);
+                let _ = reply.send(error.take().map_or(Ok(()), Err));
+            }
+        }
+    }
+    // every sender is gone. rows queued since the last flush still go in
+    commit(&mut conn, &mut pending, &mut error);
+}
+
+fn spawn_writer(app_data_dir: &str) -> SqlResult<std::sync::mpsc::Sender<WriterMsg>> {
+    let conn = Connection::open(db_path(app_data_dir))?;
+    conn.execute_batch("PRAGMA synchronous=NORMAL;")?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::Builder::new()
+        .name("db-writer".into())
+        .spawn(move || run_writer(conn, rx))
+        .expect("Failed to spawn DB writer thread");
+    Ok(tx)
+}
+
+// writes all rows in one transaction
+fn write_images(conn: &mut Connection, images: &[ImageInfo]) -> SqlResult<()> {
+    if images.is_empty() {
+        return Ok(());
+    }
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT OR REPLACE INTO images
+                (path, name, size, created_at, modified_at, phash, sha256, exif_json,
+                 phash_rotations, hash_algo, hashes_computed, format, pixels, orientation,
+                 tile_hashes, metadata_json, ahash, phash_mirror, place, metadata_level)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                     ?17, ?18, ?19, ?20)",
+        )?;
+        let mut unquarantine = tx.prepare_cached("DELETE FROM quarantine WHERE path = ?1")?;
+        for img in images {
+            let exif_json = img
+                .exif
+                .as_ref()
+                .and_then(|e| serde_json::to_string(e).ok());
+            let rotations_json = img
+                .phash_rotations
+                .as_ref()
+                .and_then(|r| serde_json::to_string(r).ok());
+            let tiles_json = img
+                .tile_hashes
+                .as_ref()
+                .and_then(|t| serde_json::to_string(t).ok());
+            let metadata_json = img
+                .metadata
+                .as_ref()
+                .and_then(|m| serde_json::to_string(m).ok());
+            stmt.execute(params![
+                img.path,
+                img.name,
+                img.size as i64,
+                img.created_at,
+                img.modified_at,
+                img.phash.map(|h| h as i64),
+                img.sha256,
+                exif_json,
+                rotations_json,
+                img.hash_algo.as_str(),
+                img.hashes_computed,
+                image_format(&img.path),
+                exif_pixels(img.exif.as_ref()),
+                image_shape(img.exif.as_ref()),
+                tiles_json,
+                metadata_json,
+                img.ahash.map(|h| h as i64),
+                img.phash_mirror.map(|h| h as i64),
+                img.place,
+                img.metadata_level.as_str(),
+            ])?;
+            // it decoded this time, so whatever was wrong with it has been fixed
+            if img.phash.is_some() {
+                unquarantine.execute(params![img.path])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// lowercase extension with the common aliases folded together
+fn image_format(path: &str) -> Option<String> {
+    let ext = Path::new(path).extension()?.to_str()?.to_lowercase();
+    Some(match ext.as_str() {
+        "jpg" => "jpeg".to_string(),
+        "tif" => "tiff".to_string(),
+        _ => ext,
+    })
+}
+
+fn exif_pixels(exif: Option<&ExifData>) -> Option<i64> {
+    let e = exif?;
+    Some(e.width? as i64 * e.height? as i64)
+}
+
+// portrait/landscape as displayed. orientations 5-8 are a quarter turn, which swaps the
+// stored width and height
+fn image_shape(exif: Option<&ExifData>) -> Option<&'static str> {
+    let e = exif?;
+    let (w, h) = (e.width?, e.height?);
+    let (w, h) = if matches!(e.orientation, Some(5..=8)) {
+        (h, w)
+    } else {
+        (w, h)
+    };
+    Some(match w.cmp(&h) {
+        std::cmp::Ordering::Greater => "landscape",
+        std::cmp::Ordering::Less => "portrait",
+        std::cmp::Ordering::Equal => "square",
+    })
+}
+
+// drops rows for files that no longer exist, along with their thumbnails
+fn cache_remove(paths: &[String]) -> SqlResult<()> {
+    let mut conn = db().lock().unwrap();
+    let tx = conn.transaction()?;
+    {
+        let mut images = tx.prepare_cached("DELETE FROM images WHERE path = ?1")?;
+        let mut thumbs = tx.prepare_cached("DELETE FROM thumbnails WHERE path = ?1")?;
+        for path in paths {
+            images.execute(params![path])?;
+            thumbs.execute(params![path])?;
+        }
+    }
+    prune_user_data(&tx)?;
+    tx.commit()
+}
+
+fn thumb_cache_get(path: &str, mtime: i64, max_edge: u32) -> Option<Vec<u8>> {
+    let conn = read_db().ok()?;
+    conn.query_row(
+        "SELECT data FROM thumbnails WHERE path = ?1 AND max_edge = ?2 AND modified_at = ?3",
+        params![path, max_edge, mtime],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+fn thumb_cache_set(path: &str, mtime: i64, max_edge: u32, data: &[u8]) -> SqlResult<()> {
+    let conn = db().lock().unwrap();
+    conn.execute(
+        "INSERT OR REPLACE INTO thumbnails (path, max_edge, modified_at, data)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![path, max_edge, mtime, data],
+    )?;
+    Ok(())
+}
+
+fn color_cache_get(path: &str, mtime: i64) -> Option<ColorSignature> {
+    let conn = read_db().ok()?;
+    let json: String = conn
+        .query_row(
+            "SELECT signature FROM color_signatures WHERE path = ?1 AND modified_at = ?2",
+            params![path, mtime],
+            |row| row.get(0),
+        )
+        .ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+fn color_cache_set(path: &str, mtime: i64, signature: &ColorSignature) -> SqlResult<()> {
+    let json = serde_json::to_string(signature).unwrap_or_default();
+    let conn = db().lock().unwrap();
+    conn.execute(
+        "INSERT OR REPLACE INTO color_signatures (path, modified_at, signature)
+         VALUES (?1, ?2, ?3)",
+        params![path, mtime, json],
+    )?;
+    Ok(())
+}
+
+fn thumb_cache_stats() -> SqlResult<ThumbnailCacheStats> {
+    let conn = db().lock().unwrap();
+    conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(length(data)), 0) FROM thumbnails",
+        [],
+        |row| {
+            Ok(ThumbnailCacheStats {
+                count: row.get::<_, i64>(0)? as u64,
+                bytes: row.get::<_, i64>(1)? as u64,
+            })
+        },
+    )
+}
+
+// size of the DB file and its WAL, which holds recent writes until a checkpoint
+fn db_file_size(conn: &Connection) -> u64 {
+    let Some(path) = conn.path() else {
+        return 0;
+    };
+    let size = |p: &str| fs::metadata(p).map(|m| m.len()).unwrap_or(0);
+    size(path) + size(&format!("{}-wal", path))
+}
+
+// removes cache rows for files under the scanned folders that no longer exist. a row
+// the scan didn't produce isn't enough on its own: a non-recursive or filtered scan
+// skips files that are still there, and rows under any other folder are left alone
+// entirely, so the cache can hold a library spread over many
+fn cache_prune(roots: &[String], valid_paths: &[String]) -> SqlResult<usize> {
+    let missing: Vec<String> = {
+        let conn = db().lock().unwrap();
+        conn.execute_batch(
+            "CREATE TEMP TABLE IF NOT EXISTS valid_paths (path TEXT PRIMARY KEY);
+             DELETE FROM valid_paths;",
+        )?;
+        {
+            let mut stmt = conn.prepare("INSERT INTO valid_paths VALUES (?1)")?;
+            for path in valid_paths {
+                stmt.execute(params![path])?;
+            }
+        }
+        let mut stmt = conn.prepare(
+            "SELECT path FROM images
+             WHERE substr(path, 1, length(?1)) = ?1
+               AND path NOT IN (SELECT path FROM valid_paths)",
+        )?;
+        let mut missing = Vec::new();
+        let mut listings = std::collections::HashMap::new();
+        for root in roots {
+            // an unplugged drive or unmounted share would make everything look deleted
+            if !Path::new(root).is_dir() {
+                continue;
+            }
+            for path in stmt.query_map(params![folder_prefix(root)], |row| row.get(0))? {
+                let path: String = path?;
+                if !still_exists(&path, &mut listings) {
+                    missing.push(path);
+                }
+            }
+        }
+        missing
+    };
+    cache_remove(&missing)?;
+    Ok(missing.len())
+}
+
+// drops tags and ratings for paths no longer in the cache, then tags nothing uses
+fn prune_user_data(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "DELETE FROM image_tags WHERE path NOT IN (SELECT path FROM images);
+         DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM image_tags);
+         DELETE FROM ratings WHERE path NOT IN (SELECT path FROM images);
+         DELETE FROM color_signatures WHERE path NOT IN (SELECT path FROM images);
+         DELETE FROM quarantine WHERE path NOT IN (SELECT path FROM images);",
+    )
+}
+
+fn system_time_to_unix(t: SystemTime) -> i64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn compute_exif(bytes: &[u8]) -> Option<ExifData> {
+    compute_exif_from(&mut std::io::Cursor::new(bytes))
+}
+
+// reads only as far into the container as the EXIF block
+fn compute_exif_file(path: &Path) -> Option<ExifData> {
+    let file = fs::File::open(path).ok()?;
+    compute_exif_from(&mut std::io::BufReader::new(file))
+}
+
+fn compute_exif_from<R: std::io::BufRead + std::io::Seek>(reader: &mut R) -> Option<ExifData> {
+    let exif = exif::Reader::new().read_from_container(reader).ok()?;
+
+    let get_str = |tag: Tag| -> Option<String> {
+        exif.get_field(tag, In::PRIMARY)
+            .map(|f: &exif::Field| f.display_value().to_string())
+    };
+
+    let get_u32 = |tag: Tag| -> Option<u32> {
+        exif.get_field(tag, In::PRIMARY).and_then(|f: &exif::Field| {
+            match &f.value {
+                Value::Long(v) => v.first().copied(),
+                Value::Short(v) => v.first().map(|&x| x as u32),
+                _ => None,
+            }
+        })
+    };
+
+    // rationals with a zero denominator show up in the wild, treat them as missing
+    let get_rational = |tag: Tag| -> Option<exif::Rational> {
+        match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Rational(v) => v.first().copied().filter(|r| r.denom != 0),
+            _ => None,
+        }
+    };
+
+    // the string as stored. display_value rewrites dates as "2024-06-01 10:15:30", which
+    // isn't the EXIF layout exif_timestamp parses
+    let get_ascii = |tag: Tag| -> Option<String> {
+        match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(v) => v
+                .first()
+                .map(|s| String::from_utf8_lossy(s).trim().to_string()),
+            _ => None,
+        }
+    };
+
+    // prefer DateTimeOriginal (when the photo was taken) over DateTime (when it was saved/edited),
+    // each paired with its own offset and sub-second tags
+    let date_parts = get_ascii(Tag::DateTimeOriginal)
+        .map(|s| {
+            let offset = get_ascii(Tag::OffsetTimeOriginal);
+            (s, offset, get_ascii(Tag::SubSecTimeOriginal))
+        })
+        .or_else(|| {
+            get_ascii(Tag::DateTime)
+                .map(|s| (s, get_ascii(Tag::OffsetTime), get_ascii(Tag::SubSecTime)))
+        });
+    let (date, date_ms) = match date_parts {
+        Some((s, offset, subsec)) => {
+            let date = exif_timestamp(&s, offset.as_deref());
+            let millis = subsec.as_deref().and_then(subsec_millis).unwrap_or(0);
+            (date, date.map(|d| d * 1000 + millis))
+        }
+        None => (None, None),
+    };
+
+    Some(ExifData {
+        date,
+        date_ms,
+        make: get_str(Tag::Make).map(|s: String| s.trim_matches('"').to_string()),
+        model: get_str(Tag::Model).map(|s: String| s.trim_matches('"').to_string()),
+        width: get_u32(Tag::PixelXDimension),
+        height: get_u32(Tag::PixelYDimension),
+        lat: gps_coord(&exif, Tag::GPSLatitude, Tag::GPSLatitudeRef),
+        lon: gps_coord(&exif, Tag::GPSLongitude, Tag::GPSLongitudeRef),
+        orientation: get_u32(Tag::Orientation),
+        iso: get_u32(Tag::PhotographicSensitivity),
+        f_number: get_rational(Tag::FNumber).map(|r| r.to_f64()),
+        exposure_time: get_rational(Tag::ExposureTime).and_then(format_exposure),
+        focal_length: get_rational(Tag::FocalLength).map(|r| r.to_f64()),
+        lens_model: get_str(Tag::LensModel).map(|s: String| s.trim_matches('"').to_string()),
+    })
+}
+
+// EXIF datetimes are camera-local wall clock time. the offset tag (Exif 2.31+) says how
+// far that was from UTC; older cameras don't write it, so those are taken as UTC
+fn exif_timestamp(datetime: &str, offset: Option<&str>) -> Option<i64> {
+    let naive =
+        chrono::NaiveDateTime::parse_from_str(datetime.trim_matches('"'), "%Y:%m:%d %H:%M:%S")
+            .ok()?;
+    let offset_secs = offset.and_then(parse_utc_offset).unwrap_or(0);
+    Some(naive.and_utc().timestamp() - offset_secs)
+}
+
+// SubSecTime holds the digits after the decimal point: "5" -> 500ms, "123456" -> 123ms
+fn subsec_millis(s: &str) -> Option<i64> {
+    let digits = s.trim_matches('"').trim();
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let padded = format!("{:0<3}", &digits[..digits.len().min(3)]);
+    padded.parse().ok()
+}
+
+// "+09:00" / "-05:30" -> seconds east of UTC
+fn parse_utc_offset(s: &str) -> Option<i64> {
+    let s = s.trim_matches('"').trim();
+    let sign = match s.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let (hours, minutes) = s[1..].split_once(':')?;
+    let hours: i64 = hours.parse().ok()?;
+    let minutes: i64 = minutes.parse().ok()?;
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+// sub-second exposures read as a fraction of a second, longer ones as plain seconds
+fn format_exposure(r: exif::Rational) -> Option<String> {
+    if r.num == 0 {
+        return None;
+    }
+    if r.num < r.denom {
+        Some(format!("1/{}", (r.denom as f64 / r.num as f64).round()))
+    } else {
+        Some(format!("{}", r.to_f64()))
+    }
+}
+
+// GPS coords are stored as three rationals (degrees, minutes, seconds) plus an N/S or
+// E/W ref tag — convert to signed decimal degrees
+fn gps_coord(exif: &exif::Exif, tag: Tag, ref_tag: Tag) -> Option<f64> {
+    let dms = match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(v) if v.len() >= 3 => v,
+        _ => return None,
+    };
+    let deg = dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0;
+    if !deg.is_finite() {
+        return None;
+    }
+
+    let negative = match exif.get_field(ref_tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(v)) => v
+            .first()
+            .and_then(|r| r.first())
+            .is_some_and(|&c| c == b'S' || c == b'W'),
+        _ => false,
+    };
+    Some(if negative { -deg } else { deg })
+}
+
+// offset of the first occurrence of needle in haystack
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum XmpField {
+    Title,
+    Description,
+    Keywords,
+    Copyright,
+}
+
+// pulls the Dublin Core fields out of an XMP packet. title/description/copyright are
+// language alternatives (the first, normally x-default, wins), keywords are a bag
+fn parse_xmp(xml: &str) -> ImageMetadata {
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut meta = ImageMetadata::default();
+    let mut field: Option<XmpField> = None;
+    let mut item: Option<String> = None; // text of the rdf:li being read
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => match e.name().as_ref() {
+                b"dc:title" => field = Some(XmpField::Title),
+                b"dc:description" => field = Some(XmpField::Description),
+                b"dc:subject" => field = Some(XmpField::Keywords),
+                b"dc:rights" => field = Some(XmpField::Copyright),
+                b"rdf:li" if field.is_some() => item = Some(String::new()),
+                _ => {}
+            },
+            Ok(Event::Text(t)) => {
+                if let (Some(text), Ok(s)) = (item.as_mut(), t.decode()) {
+                    text.push_str(&s);
+                }
+            }
+            // entities come through on their own, e.g. the "&amp;" in "Smith &amp; Co"
+            Ok(Event::GeneralRef(r)) => {
+                if let Some(text) = item.as_mut() {
+                    if let Ok(Some(c)) = r.resolve_char_ref() {
+                        text.push(c);
+                    } else if let Some(s) = r
+                        .decode()
+                        .ok()
+                        .and_then(|name| quick_xml::escape::resolve_predefined_entity(&name))
+                    {
+                        text.push_str(s);
+                    }
+                }
+            }
+            Ok(Event::End(e)) => match e.name().as_ref() {
+                b"rdf:li" => {
+                    let value = item.take().map(|t| t.trim().to_string());
+                    let Some(value) = value.filter(|v| !v.is_empty()) else {
+                        continue;
+                    };
+                    match field {
+                        Some(XmpField::Title) => {
+                            meta.title.get_or_insert(value);
+                        }
+                        Some(XmpField::Description) => {
+                            meta.description.get_or_insert(value);
+                        }
+                        Some(XmpField::Copyright) => {
+                            meta.copyright.get_or_insert(value);
+                        }
+                        Some(XmpField::Keywords) => meta.keywords.push(value),
+                        None => {}
+                    }
+                }
+                b"dc:title" | b"dc:description" | b"dc:subject" | b"dc:rights" => field = None,
+                _ => {}
+            },
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    meta
+}
+
+// the XMP packet embedded in a file, located by its wrapper element so it works the same
+// in JPEG APP1, PNG iTXt, TIFF and WebP
+fn embedded_xmp(bytes: &[u8]) -> Option<&str> {
+    let start = find_bytes(bytes, b"<x:xmpmeta")?;
+    let end_tag = b"</x:xmpmeta>";
+    let end = start + find_bytes(&bytes[start..], end_tag)? + end_tag.len();
+    std::str::from_utf8(&bytes[start..end]).ok()
+}
+
+// IPTC-IIM from a JPEG's Photoshop APP13 segment: image resource 0x0404 holds the
+// datasets, of which record 2 has the caption fields
+fn iptc_metadata(bytes: &[u8]) -> Option<ImageMetadata> {
+    let be16 = |b: &[u8], at: usize| -> Option<usize> {
+        Some(u16::from_be_bytes(b.get(at..at + 2)?.try_into().ok()?) as usize)
+    };
+    let be32 = |b: &[u8], at: usize| -> Option<usize> {
+        Some(u32::from_be_bytes(b.get(at..at + 4)?.try_into().ok()?) as usize)
+    };
+
+    let header = b"Photoshop 3.0\0";
+    let mut pos = find_bytes(bytes, header)? + header.len();
+    // 8BIM resources: signature, id, padded Pascal name, size, data padded to even
+    let iim = loop {
+        if bytes.get(pos..pos + 4)? != b"8BIM" {
+            return None;
+        }
+        let id = be16(bytes, pos + 4)?;
+        let name_len = *bytes.get(pos + 6)? as usize;
+        let size_at = pos + 6 + ((name_len + 2) & !1);
+        let size = be32(bytes, size_at)?;
+        let data = bytes.get(size_at + 4..size_at + 4 + size)?;
+        if id == 0x0404 {
+            break data;
+        }
+        pos = size_at + 4 + ((size + 1) & !1);
+    };
+
+    let mut meta = ImageMetadata::default();
+    let mut pos = 0;
+    while iim.get(pos) == Some(&0x1C) {
+        let (record, dataset) = (*iim.get(pos + 1)?, *iim.get(pos + 2)?);
+        let len = be16(iim, pos + 3)?;
+        // extended-length datasets never carry the short text fields read here
+        if len & 0x8000 != 0 {
+            break;
+        }
+        let value = String::from_utf8_lossy(iim.get(pos + 5..pos + 5 + len)?)
+            .trim()
+            .to_string();
+        if record == 2 && !value.is_empty() {
+            match dataset {
+                5 => meta.title = Some(value),
+                25 => meta.keywords.push(value),
+                116 => meta.copyright = Some(value),
+                120 => meta.description = Some(value),
+                _ => {}
+            }
+        }
+        pos += 5 + len;
+    }
+    Some(meta)
+}
+
+// "IMG_1.xmp" (Lightroom, Capture One) or "IMG_1.jpg.xmp" (darktable), with its mtime
+fn find_sidecar(path: &Path) -> Option<(std::path::PathBuf, i64)> {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".xmp");
+    [
+        path.with_extension("xmp"),
+        std::path::PathBuf::from(appended),
+    ]
+    .into_iter()
+    .find_map(|p| {
+        let mtime = fs::metadata(&p).and_then(|m| m.modified()).ok()?;
+        Some((p, system_time_to_unix(mtime)))
+    })
+}
+
+fn read_metadata(path: &Path) -> Option<ImageMetadata> {
+    use std::io::Read;
+    let sidecar = find_sidecar(path);
+    let from_sidecar = sidecar
+        .as_ref()
+        .and_then(|(p, _)| fs::read_to_string(p).ok())
+        .map(|xml| parse_xmp(&xml))
+        .unwrap_or_default();
+
+    let mut head = Vec::new();
+    if let Ok(file) = fs::File::open(path) {
+        let _ = file.take(METADATA_SCAN_BYTES).read_to_end(&mut head);
+    }
+
+    let mut meta = from_sidecar.or(embedded_metadata(&head));
+    meta.sidecar_modified_at = sidecar.map(|(_, mtime)| mtime);
+    // keep an empty record if there's a sidecar, so its mtime is remembered
+    (!meta.is_empty() || meta.sidecar_modified_at.is_some()).then_some(meta)
+}
+
+// embedded XMP, then IPTC, from the first METADATA_SCAN_BYTES of a file
+fn embedded_metadata(head: &[u8]) -> ImageMetadata {
+    let embedded = embedded_xmp(head).map(parse_xmp).unwrap_or_default();
+    let iptc = iptc_metadata(head).unwrap_or_default();
+    embedded.or(iptc)
+}
+
+// HEIF files are ISO-BMFF: an `ftyp` box at offset 4 followed by the major brand
+fn is_heif(bytes: &[u8]) -> bool {
+    const BRANDS: &[&[u8; 4]] = &[
+        b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+    ];
+    bytes.len() >= 12 && &bytes[4..8] == b"ftyp" && BRANDS.iter().any(|b| &bytes[8..12] == *b)
+}
+
+// AVIF is ISO-BMFF too, but decoded by the image crate rather than libheif
+fn is_avif(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[4..8] == b"ftyp" && matches!(&bytes[8..12], b"avif" | b"avis")
+}
+
+// the image crate decodes AVIF through the system libdav1d, only in builds with the
+// `avif` feature
+#[cfg(feature = "avif")]
+fn avif_supported() -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(not(feature = "avif"))]
+fn avif_supported() -> Result<(), String> {
+    static WARN: std::sync::Once = std::sync::Once::new();
+    WARN.call_once(|| {
+        eprintln!("AVIF decoding not available in this build (enable the `avif` feature), skipping pHash for those files")
+    });
+    Err("AVIF decoding not available in this build".to_string())
+}
+
+#[cfg(feature = "heic")]
+fn decode_heif(bytes: &[u8]) -> Result<image::DynamicImage, String> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let lib = LibHeif::new();
+    let ctx = HeifContext::read_from_bytes(bytes).map_err(|e| format!("heif read failed: {e}"))?;
+    let handle = ctx
+        .primary_image_handle()
+        .map_err(|e| format!("heif read failed: {e}"))?;
+    let img = lib
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+        .map_err(|e| format!("heif decode failed: {e}"))?;
+
+    // the interleaved plane is padded to `stride`, so copy it row by row
+    let plane = img
+        .planes()
+        .interleaved
+        .ok_or("heif decode produced no RGB plane")?;
+    let row_len = plane.width as usize * 3;
+    let mut buf = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        buf.extend_from_slice(&row[..row_len]);
+    }
+    image::RgbImage::from_raw(plane.width, plane.height, buf)
+        .map(image::DynamicImage::ImageRgb8)
+        .ok_or_else(|| "heif plane has unexpected size".to_string())
+}
+
+#[cfg(not(feature = "heic"))]
+fn decode_heif(_bytes: &[u8]) -> Result<image::DynamicImage, String> {
+    static WARN: std::sync::Once = std::sync::Once::new();
+    WARN.call_once(|| {
+        eprintln!("HEIC/HEIF decoding not available in this build (enable the `heic` feature), skipping pHash for those files")
+    });
+    Err("HEIC/HEIF decoding not available in this build".to_string())
+}
+
+// header-only dimensions for HEIF, the image crate can't read these
+#[cfg(feature = "heic")]
+fn heif_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let ctx = libheif_rs::HeifContext::read_from_bytes(bytes).ok()?;
+    let handle = ctx.primary_image_handle().ok()?;
+    Some((handle.width(), handle.height()))
+}
+
+#[cfg(not(feature = "heic"))]
+fn heif_dimensions(_bytes: &[u8]) -> Option<(u32, u32)> {
+    None
+}
+
+fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| RAW_EXTS.contains(&ext.to_lowercase().as_str()))
+}
+
+// RAW containers are TIFF underneath. walks the IFD0 chain plus any SubIFDs and returns
+// the largest embedded JPEG, which is the camera's full-size (or near) preview
+fn raw_preview(bytes: &[u8]) -> Option<&[u8]> {
+    let le = match bytes.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |off: usize| -> Option<u16> {
+        let b: [u8; 2] = bytes.get(off..off.checked_add(2)?)?.try_into().ok()?;
+        Some(if le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    };
+    let u32_at = |off: usize| -> Option<u32> {
+        let b: [u8; 4] = bytes.get(off..off.checked_add(4)?)?.try_into().ok()?;
+        Some(if le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    };
+
+    let mut queue = vec![u32_at(4)? as usize];
+    let mut seen = std::collections::HashSet::new();
+    let mut best: Option<&[u8]> = None;
+    while let Some(ifd) = queue.pop() {
+        // guards against offset loops in malformed files
+        if ifd == 0 || seen.len() >= 64 || !seen.insert(ifd) {
+            continue;
+        }
+        let Some(count) = u16_at(ifd) else { continue };
+        let (mut jpeg_off, mut jpeg_len) = (None, None);
+        let (mut strip_off, mut strip_len, mut compression) = (None, None, None);
+        for i in 0..count as usize {
+            let entry = ifd + 2 + i * 12;
+            let (Some(tag), Some(kind), Some(n)) =
+                (u16_at(entry), u16_at(entry + 2), u32_at(entry + 4))
+            else {
+                break;
+            };
+            // a single SHORT sits in the first two bytes of the value field
+            let value = if kind == 3 {
+                u16_at(entry + 8).map(u32::from)
+            } else {
+                u32_at(entry + 8)
+            };
+            match tag {
+                0x0103 => compression = value,
+                0x0111 if n == 1 => strip_off = value,
+                0x0117 if n == 1 => strip_len = value,
+                0x0201 => jpeg_off = value,
+                0x0202 => jpeg_len = value,
+                // SubIFDs: inline when there's one, otherwise an offset to the list
+                0x014a if n == 1 => queue.extend(value.map(|v| v as usize)),
+                0x014a => {
+                    if let Some(list) = value {
+                        for k in 0..n.min(16) as usize {
+                            queue.extend(u32_at(list as usize + k * 4).map(|v| v as usize));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        queue.extend(u32_at(ifd + 2 + count as usize * 12).map(|v| v as usize));
+
+        // old-style JPEG (6) and DNG lossy JPEG (34892) strips are plain JPEG streams
+        let strip = match compression {
+            Some(6 | 34892) => strip_off.zip(strip_len),
+            _ => None,
+        };
+        for (off, len) in [jpeg_off.zip(jpeg_len), strip].into_iter().flatten() {
+            let (off, len) = (off as usize, len as usize);
+            let Some(data) = off.checked_add(len).and_then(|end| bytes.get(off..end)) else {
+                continue;
+            };
+            if data.starts_with(&[0xFF, 0xD8]) && best.is_none_or(|b| data.len() > b.len()) {
+                best = Some(data);
+            }
+        }
+    }
+    best
+}
+
+// the bytes that actually get decoded: the file itself, or the embedded preview for RAW
+fn decodable_bytes<'a>(path: &Path, bytes: &'a [u8]) -> Result<&'a [u8], String> {
+    if is_raw(path) {
+        return raw_preview(bytes)
+            .ok_or_else(|| "no embedded JPEG preview in RAW file".to_string());
+    }
+    Ok(bytes)
+}
+
+// a reason a user can act on: an unsupported format needs a different build, a corrupt
+// file needs replacing
+fn describe_decode_error(e: image::ImageError) -> String {
+    match e {
+        image::ImageError::Unsupported(e) => format!("unsupported image: {e}"),
+        image::ImageError::Decoding(e) => format!("corrupt or truncated image: {e}"),
+        image::ImageError::Limits(e) => format!("image too large to decode: {e}"),
+        e => format!("image load failed: {e}"),
+    }
+}
+
+// the image crate converts CMYK/YCCK JPEGs and CMYK TIFFs to RGB while decoding, so
+// everything past here is RGB(A) or grayscale
+fn load_image(bytes: &[u8], limits: image::Limits) -> Result<image::DynamicImage, String> {
+    if is_heif(bytes) {
+        return decode_heif(bytes);
+    }
+    if is_avif(bytes) {
+        avif_supported()?;
+    }
+    let mut reader = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| format!("image load failed: {e}"))?;
+    reader.limits(limits);
+    reader.decode().map_err(describe_decode_error)
+}
+
+// rotates/flips a decoded image so it's upright according to its EXIF orientation
+fn apply_orientation(img: image::DynamicImage, orientation: u32) -> image::DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// decodes and normalizes orientation. HEIF is skipped because libheif already applies
+// the container's rotation and the EXIF tag would rotate it a second time
+fn load_oriented(
+    bytes: &[u8],
+    orientation: Option<u32>,
+    limits: image::Limits,
+) -> Result<image::DynamicImage, String> {
+    let img = load_image(bytes, limits)?;
+    match orientation {
+        Some(o) if !is_heif(bytes) => Ok(apply_orientation(img, o)),
+        _ => Ok(img),
+    }
+}
+
+// 16-bit and float images, which hash_input keeps at 16 bits
+fn is_high_bit_depth(img: &image::DynamicImage) -> bool {
+    use image::DynamicImage::*;
+    matches!(
+        img,
+        ImageLuma16(_)
+            | ImageLumaA16(_)
+            | ImageRgb16(_)
+            | ImageRgba16(_)
+            | ImageRgb32F(_)
+            | ImageRgba32F(_)
+    )
+}
+
+// luma at w x h for hashing, on the source's own scale: 0-255 for 8-bit images and
+// 0-65535 for deeper ones, so a dark 16-bit scan keeps its fine gradations instead of
+// collapsing into ties. converting before the resize means it runs on a single channel
+// whatever the source was, and grayscale sources are used as they are
+fn hash_input(img: &image::DynamicImage, w: u32, h: u32) -> Vec<f64> {
+    let filter = image::imageops::FilterType::Lanczos3;
+    if is_high_bit_depth(img) {
+        let luma = match img {
+            image::DynamicImage::ImageLuma16(gray) => std::borrow::Cow::Borrowed(gray),
+            _ => std::borrow::Cow::Owned(img.to_luma16()),
+        };
+        let small = image::imageops::resize(&*luma, w, h, filter);
+        return small.into_raw().into_iter().map(f64::from).collect();
+    }
+    let luma = match img {
+        image::DynamicImage::ImageLuma8(gray) => std::borrow::Cow::Borrowed(gray),
+        _ => std::borrow::Cow::Owned(img.to_luma8()),
+    };
+    let small = image::imageops::resize(&*luma, w, h, filter);
+    small.into_raw().into_iter().map(f64::from).collect()
+}
+
+fn compute_phash(img: &image::DynamicImage) -> u64 {
+    // dHash: resize to 9x8, compare adjacent pixels row-wise -> 64-bit hash
+    let pixels = hash_input(img, 9, 8);
+
+    let mut hash: u64 = 0;
+    for row in 0..8 {
+        for col in 0..8 {
+            let left = pixels[row * 9 + col];
+            let right = pixels[row * 9 + col + 1];
+            hash = (hash << 1) | if left > right { 1 } else { 0 };
+        }
+    }
+
+    hash
+}
+
+// DCT pHash: 32x32 grayscale -> 2D DCT -> keep the top-left 8x8 low frequencies ->
+// one bit per coefficient above the median. survives gamma/brightness shifts and JPEG
+// recompression much better than dHash
+fn compute_dct_phash(img: &image::DynamicImage) -> u64 {
+    const N: usize = 32;
+    const K: usize = 8;
+
+    let pixels = hash_input(img, N as u32, N as u32);
+
+    // only the first K DCT-II basis functions are needed
+    let mut cos = [[0.0f64; N]; K];
+    for (u, row) in cos.iter_mut().enumerate() {
+        for (x, c) in row.iter_mut().enumerate() {
+            *c = (((2 * x + 1) * u) as f64 * std::f64::consts::PI / (2 * N) as f64).cos();
+        }
+    }
+
+    let mut coeffs = [0.0f64; K * K];
+    for u in 0..K {
+        for v in 0..K {
+            let mut sum = 0.0;
+            for y in 0..N {
+                for x in 0..N {
+                    sum += pixels[y * N + x] * cos[u][y] * cos[v][x];
+                }
+            }
+            coeffs[u * K + v] = sum;
+        }
+    }
+
+    // the DC term is just overall brightness and would skew the median, leave it out
+    let mut ac: Vec<f64> = coeffs[1..].to_vec();
+    ac.sort_by(|a, b| a.total_cmp(b));
+    let median = ac[ac.len() / 2];
+
+    coeffs
+        .iter()
+        .fold(0u64, |h, &c| (h << 1) | u64::from(c > median))
+}
+
+// dHash after first scaling to a fixed square with a smoothing filter. an original and a
+// small re-compressed export then go through the same final 9x8 resize, and the blocky
+// JPEG noise that pushed their plain dHashes apart is averaged out on the way
+fn compute_normalized_dhash(img: &image::DynamicImage) -> u64 {
+    let (edge, filter) = (HASH_NORMALIZE_EDGE, image::imageops::FilterType::Triangle);
+    let normalized = if is_high_bit_depth(img) {
+        image::DynamicImage::ImageLuma16(image::imageops::resize(
+            &img.to_luma16(),
+            edge,
+            edge,
+            filter,
+        ))
+    } else {
+        image::DynamicImage::ImageLuma8(image::imageops::resize(
+            &img.to_luma8(),
+            edge,
+            edge,
+            filter,
+        ))
+    };
+    compute_phash(&normalized)
+}
+
+// grayscale dilation: each pixel becomes the brightest within `radius`. separable, rows
+// then columns
+fn local_max(img: &image::GrayImage, radius: u32) -> image::GrayImage {
+    let (w, h) = img.dimensions();
+    let rows = image::GrayImage::from_fn(w, h, |x, y| {
+        let (lo, hi) = (x.saturating_sub(radius), (x + radius).min(w - 1));
+        image::Luma([(lo..=hi).map(|x| img.get_pixel(x, y)[0]).max().unwrap_or(0)])
+    });
+    image::GrayImage::from_fn(w, h, |x, y| {
+        let (lo, hi) = (y.saturating_sub(radius), (y + radius).min(h - 1));
+        image::Luma([(lo..=hi)
+            .map(|y| rows.get_pixel(x, y)[0])
+            .max()
+            .unwrap_or(0)])
+    })
+}
+
+// dHash of how far each pixel sits below the paper around it. blank paper is all zeros
+// however the scanner lit it, so only the text and drawings decide the bits. plain dHash
+// on a page is mostly comparing cells of near-equal grey, where a rescan's shading or
+// exposure flips them at random. kept at 16 bits so faint pages don't round to ties
+fn compute_document_dhash(img: &image::DynamicImage) -> u64 {
+    let edge = HASH_NORMALIZE_EDGE;
+    let small = image::imageops::resize(
+        &img.to_luma8(),
+        edge,
+        edge,
+        image::imageops::FilterType::Triangle,
+    );
+    let gray = image::imageops::blur(&small, DOCUMENT_DENOISE_SIGMA);
+    let paper = image::imageops::blur(
+        &local_max(&gray, DOCUMENT_PAPER_RADIUS),
+        DOCUMENT_PAPER_SIGMA,
+    );
+    let ink = image::ImageBuffer::from_fn(edge, edge, |x, y| {
+        let depth = paper.get_pixel(x, y)[0].saturating_sub(gray.get_pixel(x, y)[0]);
+        image::Luma([depth as u16 * 257])
+    });
+    compute_phash(&image::DynamicImage::ImageLuma16(ink))
+}
+
+// aHash: 8x8 grayscale, one bit per pixel brighter than the mean. blind to the edges
+// dHash looks at, so two images that only share a gradient pattern disagree on it
+fn compute_ahash(img: &image::DynamicImage) -> u64 {
+    let pixels = hash_input(img, 8, 8);
+    // floored, as the integer mean of 8-bit pixels always was
+    let mean = (pixels.iter().sum::<f64>() / pixels.len() as f64).floor();
+    pixels
+        .iter()
+        .fold(0u64, |h, &p| (h << 1) | u64::from(p > mean))
+}
+
+// an 8x8 aHash turned 90° clockwise. the hash is a square bitmap of the image, so this
+// is (up to resampling) the aHash of the rotated image, no decoding needed
+fn rotate_ahash(hash: u64) -> u64 {
+    let bit = |h: u64, row: usize, col: usize| (h >> (63 - (row * 8 + col))) & 1;
+    (0..64).fold(0u64, |h, i| {
+        let (row, col) = (i / 8, i % 8);
+        (h << 1) | bit(hash, 7 - col, row)
+    })
+}
+
+// an 8x8 aHash flipped left to right: each byte is one row
+fn mirror_ahash(hash: u64) -> u64 {
+    u64::from_be_bytes(hash.to_be_bytes().map(u8::reverse_bits))
+}
+
+// aHash plus its rotations and mirror image when the phash has those, laid out like
+// hash_variants so variant_distance works on it. empty if there's no aHash
+fn ahash_variants(ahash: Option<u64>, rotated: bool, mirrored: bool) -> Vec<u64> {
+    let Some(h) = ahash else {
+        return Vec::new();
+    };
+    let mut variants = vec![h];
+    if rotated {
+        let r90 = rotate_ahash(h);
+        let r180 = rotate_ahash(r90);
+        variants.extend([r90, r180, rotate_ahash(r180)]);
+    }
+    if mirrored {
+        variants.push(mirror_ahash(h));
+    }
+    variants
+}
+
+// the aHash half of the similarity check. images without an aHash (from before it was
+// stored) pass, so they still group on phash alone
+fn ahash_agrees(a: &[u64], b: &[u64], threshold: u32) -> bool {
+    variant_distance(a, b).is_none_or(|d| d <= threshold)
+}
+
+fn compute_hash(img: &image::DynamicImage, algo: HashAlgo) -> u64 {
+    match algo {
+        HashAlgo::Dhash => compute_phash(img),
+        HashAlgo::Phash => compute_dct_phash(img),
+        HashAlgo::DhashNormalized => compute_normalized_dhash(img),
+        HashAlgo::DhashDocument => compute_document_dhash(img),
+    }
+}
+
+// hashes of the image turned 90/180/270°, for matching copies that were re-saved rotated
+fn compute_rotation_hashes(img: &image::DynamicImage, algo: HashAlgo) -> Vec<u64> {
+    vec![
+        compute_hash(&img.rotate90(), algo),
+        compute_hash(&img.rotate180(), algo),
+        compute_hash(&img.rotate270(), algo),
+    ]
+}
+
+// hashes of TILE_GRID x TILE_GRID overlapping square tiles, each half the short edge.
+// sizing tiles by the short edge keeps them at the same scale in a crop that kept the
+// full height (or width), e.g. a square export of a landscape shot
+fn compute_tile_hashes(img: &image::DynamicImage, algo: HashAlgo) -> Vec<u64> {
+    let (w, h) = (img.width(), img.height());
+    let tile = (w.min(h) / 2).max(1);
+    let (step_x, step_y) = ((w - tile) / (TILE_GRID - 1), (h - tile) / (TILE_GRID - 1));
+    let mut hashes = Vec::with_capacity((TILE_GRID * TILE_GRID) as usize);
+    for row in 0..TILE_GRID {
+        for col in 0..TILE_GRID {
+            let region = img.crop_imm(col * step_x, row * step_y, tile, tile);
+            hashes.push(compute_hash(&region, algo));
+        }
+    }
+    hashes
+}
+
+// the small JPEG most cameras embed in IFD1. its offset is relative to the start of the
+// TIFF data, which is exactly what Exif::buf() returns
+fn embedded_thumbnail(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut cursor = std::io::Cursor::new(bytes);
+    let exif = exif::Reader::new()
+        .read_from_container(&mut cursor)
+        .ok()?;
+    let get = |tag: Tag| -> Option<usize> {
+        match &exif.get_field(tag, In::THUMBNAIL)?.value {
+            Value::Long(v) => v.first().map(|&x| x as usize),
+            _ => None,
+        }
+    };
+    let offset = get(Tag::JPEGInterchangeFormat)?;
+    let len = get(Tag::JPEGInterchangeFormatLength)?;
+    exif.buf()
+        .get(offset..offset.checked_add(len)?)
+        .map(|t| t.to_vec())
+}
+
+// decodes, orients, and shrinks to fit within max_edge, re-encoded as JPEG. uses the
+// embedded EXIF thumbnail when it's big enough, which skips decoding the full image
+fn make_thumbnail(path: &Path, bytes: &[u8], max_edge: u32) -> Result<Vec<u8>, String> {
+    let orientation = compute_exif(bytes).and_then(|e| e.orientation);
+    let embedded = embedded_thumbnail(bytes)
+        .and_then(|t| image::load_from_memory(&t).ok())
+        .filter(|t| t.width().max(t.height()) >= max_edge);
+    let img = match embedded {
+        // the embedded thumbnail is stored in sensor orientation, same as the main image
+        Some(t) => apply_orientation(t, orientation.unwrap_or(1)),
+        None => load_oriented(
+            decodable_bytes(path, bytes)?,
+            orientation,
+            image::Limits::default(),
+        )?,
+    };
+    // JPEG has no alpha channel, so flatten to RGB before encoding
+    let thumb = img.thumbnail(max_edge, max_edge).to_rgb8();
+
+    let mut buf = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, THUMBNAIL_QUALITY)
+        .encode_image(&thumb)
+        .map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+fn data_url(mime: &str, bytes: &[u8]) -> String {
+    format!(
+        "data:{};base64,{}",
+        mime,
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    )
+}
+
+fn jpeg_data_url(bytes: &[u8]) -> String {
+    data_url("image/jpeg", bytes)
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "avif" => "image/avif",
+        "jxl" => "image/jxl",
+        _ => "application/octet-stream",
+    }
+}
+
+// only files a scan has put in the cache may be read back through get_image_data
+fn is_cached_path(path: &str) -> bool {
+    let Ok(conn) = read_db() else {
+        return false;
+    };
+    conn.query_row(
+        "SELECT COUNT(*) > 0 FROM images WHERE path = ?1",
+        params![path],
+        |row| row.get(0),
+    )
+    .unwrap_or(false)
+}
+
+// streams the file through the hasher, so large files are never held in memory whole
+fn compute_sha256(path: &Path) -> std::io::Result<String> {
+    let file = fs::File::open(path)?;
+    let mut reader = std::io::BufReader::with_capacity(HASH_READ_BUF_SIZE, file);
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// RAW previews are located by offset and libheif decodes from a buffer, so those formats
+// are read whole. everything else is decoded straight from the file
+fn needs_full_read(path: &Path) -> bool {
+    is_raw(path) || is_heif_file(path)
+}
+
+// the first bytes of a file, enough to tell the ISO-BMFF brands apart
+fn file_head(path: &Path) -> Option<[u8; 12]> {
+    use std::io::Read;
+    let mut head = [0u8; 12];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut head))
+        .ok()?;
+    Some(head)
+}
+
+fn is_heif_file(path: &Path) -> bool {
+    file_head(path).is_some_and(|head| is_heif(&head))
+}
+
+fn is_avif_file(path: &Path) -> bool {
+    file_head(path).is_some_and(|head| is_avif(&head))
+}
+
+// a HEIF or AVIF file in a build without its decoder, which can't be decoded or even
+// measured however intact it is
+fn lacks_decoder(path: &Path) -> bool {
+    (!cfg!(feature = "heic") && is_heif_file(path))
+        || (!cfg!(feature = "avif") && is_avif_file(path))
+}
+
+fn is_quarantined(path: &str) -> bool {
+    read_db().is_ok_and(|conn| {
+        conn.query_row(
+            "SELECT 1 FROM quarantine WHERE path = ?1",
+            params![path],
+            |_| Ok(()),
+        )
+        .is_ok()
+    })
+}
+
+// records a decode failure as possibly corrupt. HEIC or AVIF in a build without their
+// decoder failed for lack of it, not because of the file, so they're left out
+fn quarantine(err: &ScanError) {
+    if lacks_decoder(Path::new(&err.path)) {
+        return;
+    }
+    let writer = WRITER.get().expect("DB writer not initialized");
+    let msg = WriterMsg::Quarantine {
+        path: err.path.clone(),
+        reason: err.reason.clone(),
+    };
+    if writer.send(msg).is_err() {
+        eprintln!("DB writer has stopped, quarantine entry dropped");
+    }
+}
+
+// load_oriented_file for untrusted input: the size in the header is checked against
+// max_pixels before anything is decoded, and the decoder itself runs under
+// decode_limits. files whose header can't be measured are refused
+fn decode_checked(
+    path: &Path,
+    orientation: Option<u32>,
+    max_pixels: u64,
+) -> Result<image::DynamicImage, String> {
+    let head = file_head(path).unwrap_or_default();
+    let dims = file_dimensions(path).ok_or_else(|| unmeasurable_reason(&head))?;
+    check_pixel_limit(dims, max_pixels)?;
+    load_oriented_file(path, orientation, decode_limits(max_pixels))
+}
+
+// why an image's dimensions couldn't be read: a format this build has no decoder for, or
+// a header that doesn't parse
+fn unmeasurable_reason(head: &[u8]) -> String {
+    if (!cfg!(feature = "heic") && is_heif(head)) || (!cfg!(feature = "avif") && is_avif(head)) {
+        "no decoder for this format in this build".to_string()
+    } else {
+        "image dimensions unreadable, refusing to decode".to_string()
+    }
+}
+
+// no side may exceed max_pixels and the decoder may not allocate more than
+// DECODE_BYTES_PER_PIXEL per allowed pixel, whatever the header claimed
+fn decode_limits(max_pixels: u64) -> image::Limits {
+    let side = u32::try_from(max_pixels).unwrap_or(u32::MAX);
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(side);
+    limits.max_image_height = Some(side);
+    limits.max_alloc = Some(max_pixels.saturating_mul(DECODE_BYTES_PER_PIXEL));
+    limits
+}
+
+fn check_pixel_limit((w, h): (u32, u32), max_pixels: u64) -> Result<(), String> {
+    if w as u64 * h as u64 > max_pixels {
+        return Err(format!(
+            "{}x{} is over the {} megapixel limit",
+            w,
+            h,
+            max_pixels / 1_000_000
+        ));
+    }
+    Ok(())
+}
+
+// load_oriented for a file on disk, without buffering the encoded bytes where possible
+fn load_oriented_file(
+    path: &Path,
+    orientation: Option<u32>,
+    limits: image::Limits,
+) -> Result<image::DynamicImage, String> {
+    if needs_full_read(path) {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        return load_oriented(decodable_bytes(path, &bytes)?, orientation, limits);
+    }
+    if is_avif_file(path) {
+        avif_supported()?;
+    }
+    let mut reader = image::ImageReader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| format!("image load failed: {e}"))?;
+    reader.limits(limits);
+    let img = reader.decode().map_err(describe_decode_error)?;
+    Ok(match orientation {
+        Some(o) => apply_orientation(img, o),
+        None => img,
+    })
+}
+
+// header-only dimensions, used when EXIF doesn't have them (common for PNG/WebP).
+// image::image_dimensions() only reads the file header so it's very cheap
+fn file_dimensions(path: &Path) -> Option<(u32, u32)> {
+    if !needs_full_read(path) {
+        return image::image_dimensions(path).ok();
+    }
+    bytes_dimensions(path, &fs::read(path).ok()?)
+}
+
+// file_dimensions for a file already read into memory
+fn bytes_dimensions(path: &Path, bytes: &[u8]) -> Option<(u32, u32)> {
+    if is_heif(bytes) {
+        return heif_dimensions(bytes);
+    }
+    image::ImageReader::new(std::io::Cursor::new(decodable_bytes(path, bytes).ok()?))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+// variance of the 3x3 Laplacian over the grayscale image. edges give large responses of
+// both signs, so a sharp image has a wide spread and a blurry one stays near zero
+fn laplacian_variance(img: &image::DynamicImage) -> f64 {
+    let gray = img.to_luma8();
+    let (w, h) = gray.dimensions();
+    if w < 3 || h < 3 {
+        return 0.0;
+    }
+    let px = |x: u32, y: u32| f64::from(gray.get_pixel(x, y).0[0]);
+
+    let (mut sum, mut sum_sq, mut n) = (0.0, 0.0, 0.0);
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let lap = px(x - 1, y) + px(x + 1, y) + px(x, y - 1) + px(x, y + 1) - 4.0 * px(x, y);
+            sum += lap;
+            sum_sq += lap * lap;
+            n += 1.0;
+        }
+    }
+    let mean = sum / n;
+    sum_sq / n - mean * mean
+}
+
+// process_image_file under options.file_timeout_secs. the work runs on its own thread so a
+// read hung on a dead network mount can be abandoned — the thread itself can't be killed
+// and stays blocked until the OS gives up, but the scan moves on
+fn process_file(path: &Path, options: &ScanOptions) -> Result<ProcessedImage, ScanError> {
+    let Some(secs) = options.file_timeout_secs else {
+        return process_image_file(path, options);
+    };
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (owned_path, owned_options) = (path.to_path_buf(), options.clone());
+    std::thread::spawn(move || {
+        let _ = tx.send(process_image_file(&owned_path, &owned_options));
+    });
+    rx.recv_timeout(std::time::Duration::from_secs(secs))
+        .unwrap_or_else(|_| {
+            let reason = format!("no response after {}s", secs);
+            Err(ScanError::new(path, ScanErrorKind::Timeout, reason))
+        })
+}
+
+// a cached row has every hash this scan needs: none at all, a different algorithm, or
+// missing rotations or tiles mean it doesn't. quarantined files are retried every time so
+// a fixed decoder (or file) clears them
+fn cached_hashes_ok(cached: &ImageInfo, options: &ScanOptions) -> bool {
+    !options.compute_hashes
+        || (cached.hashes_computed
+            && cached.hash_algo == options.scan_hash_algo()
+            && (!options.rotation_invariant || cached.phash_rotations.is_some())
+            && (!options.mirror_invariant || cached.phash_mirror.is_some())
+            && (!options.crop_resistant || cached.tile_hashes.is_some())
+            && (cached.phash.is_none() || cached.ahash.is_some())
+            && (cached.phash.is_some() || !is_quarantined(&cached.path)))
+}
+
+// ScanErrorKind::TooSmall for an image under min_width/min_height
+fn check_min_size(path: &Path, (w, h): (u32, u32), options: &ScanOptions) -> Result<(), ScanError> {
+    if w < options.min_width.unwrap_or(0) || h < options.min_height.unwrap_or(0) {
+        let reason = format!("{}x{} is below the minimum size", w, h);
+        return Err(ScanError::new(path, ScanErrorKind::TooSmall, reason));
+    }
+    Ok(())
+}
+
+// the perceptual hashes a scan with these options stores for a decoded image
+#[derive(Default)]
+struct PixelHashes {
+    phash: Option<u64>,
+    ahash: Option<u64>,
+    phash_rotations: Option<Vec<u64>>,
+    phash_mirror: Option<u64>,
+    tile_hashes: Option<Vec<u64>>,
+}
+
+fn pixel_hashes(img: &image::DynamicImage, options: &ScanOptions) -> PixelHashes {
+    PixelHashes {
+        phash: Some(compute_hash(img, options.scan_hash_algo())),
+        ahash: Some(compute_ahash(img)),
+        phash_rotations: options
+            .rotation_invariant
+            .then(|| compute_rotation_hashes(img, options.scan_hash_algo())),
+        phash_mirror: options
+            .mirror_invariant
+            .then(|| compute_hash(&img.fliph(), options.scan_hash_algo())),
+        tile_hashes: options
+            .crop_resistant
+            .then(|| compute_tile_hashes(img, options.scan_hash_algo())),
+    }
+}
+
+// EXIF dimensions, or the header's when EXIF has none (common for PNG/WebP)
+fn with_dimensions(exif: Option<ExifData>, dims: Option<(u32, u32)>) -> Option<ExifData> {
+    let Some((w, h)) = dims else {
+        return exif;
+    };
+    let mut exif = exif.unwrap_or_default();
+    exif.width = Some(w);
+    exif.height = Some(h);
+    Some(exif)
+}
+
+// only places already in the lookup cache — loading the geocoder is left to
+// geocode_images so a scan never pays for it
+fn cached_place_label(exif: Option<&ExifData>) -> Option<String> {
+    exif.and_then(|e| Some((e.lat?, e.lon?)))
+        .and_then(|(lat, lon)| cached_place(lat, lon))
+        .map(|p| p.label())
+}
+
+// fresh results are not written here — callers queue them for the writer thread
+fn process_image_file(
+    file_path: &Path,
+    options: &ScanOptions,
+) -> Result<ProcessedImage, ScanError> {
+    if let Some((archive, entry)) = archive_entry(file_path) {
+        let mut archive = OpenArchive::open(&archive)
+            .map_err(|e| ScanError::new(file_path, ScanErrorKind::IoError, e))?;
+        return process_archive_entry(file_path, &mut archive, &entry, options);
+    }
+    let meta = fs::metadata(file_path)
+        .map_err(|e| ScanError::new(file_path, ScanErrorKind::IoError, e))?;
+    let size = meta.len();
+    let modified = meta
+        .modified()
+        .map_err(|e| ScanError::new(file_path, ScanErrorKind::MetadataError, e))?;
+    let mtime = system_time_to_unix(modified);
+    // birthtime isn't available on most Linux filesystems; mtime is the closest stand-in
+    let created_at = system_time_to_unix(meta.created().unwrap_or(modified));
+    let path_str = file_path.to_string_lossy().to_string();
+
+    // only the header is read. files whose size can't be told go on to be decoded
+    if options.min_width.is_some() || options.min_height.is_some() {
+        if let Some(dims) = file_dimensions(file_path) {
+            check_min_size(file_path, dims, options)?;
+        }
+    }
+
+    // cache hit — skip all processing, unless this scan needs hashes the cached row doesn't
+    // have or more metadata than it was read with
+    if let Some(cached) = cache_get(&path_str, mtime, size)
+        .filter(|cached| cached.metadata_level >= options.metadata_level)
+    {
+        if cached_hashes_ok(&cached, options) {
+            // a sidecar edited (or added, or removed) since the row was cached only needs
+            // the metadata re-read, the hashes are still good
+            let cached_sidecar = cached.metadata.as_ref().and_then(|m| m.sidecar_modified_at);
+            let sidecar_fresh = options.metadata_level < MetadataLevel::Full
+                || find_sidecar(file_path).map(|(_, mtime)| mtime) == cached_sidecar;
+            // rows cached before sub-second dates were read get them backfilled from EXIF
+            let missing_ms = options.metadata_level == MetadataLevel::Full
+                && (cached.exif.as_ref()).is_some_and(|e| e.date.is_some() && e.date_ms.is_none());
+            let mut info = cached;
+            if !sidecar_fresh {
+                info.metadata = read_metadata(file_path);
+            }
+            if let Some(e) = info.exif.as_mut().filter(|_| missing_ms) {
+                let fresh = compute_exif_file(file_path).and_then(|f| f.date_ms);
+                e.date_ms = fresh.or(e.date.map(|d| d * 1000));
+            }
+            return Ok(ProcessedImage {
+                info,
+                from_cache: sidecar_fresh && !missing_ms,
+                decode_error: None,
+            });
+        }
+    }
+
+    // cache miss — process the file. each step reads it separately rather than holding
+    // the whole thing in memory, which matters with many rayon workers on large files
+    let sha256 = if options.compute_hashes {
+        compute_sha256(file_path)
+            .map(Some)
+            .map_err(|e| ScanError::new(file_path, ScanErrorKind::IoError, e))?
+    } else {
+        None
+    };
+    // EXIF comes from the file itself even for RAW, the preview's own metadata is sparse
+    let full = options.metadata_level == MetadataLevel::Full;
+    let mut exif = if full {
+        compute_exif_file(file_path)
+    } else {
+        None
+    };
+    let metadata = if full { read_metadata(file_path) } else { None };
+    // a metadata-only scan never decodes pixels
+    let (img, decode_error) = if options.compute_hashes {
+        let orientation = exif.as_ref().and_then(|e| e.orientation);
+        match decode_checked(file_path, orientation, options.max_pixels) {
+            Ok(img) => (Some(img), None),
+            Err(e) => (
+                None,
+                Some(ScanError::new(file_path, ScanErrorKind::DecodeError, e)),
+            ),
+        }
+    } else {
+        (None, None)
+    };
+    let hashes = img
+        .as_ref()
+        .map(|i| pixel_hashes(i, options))
+        .unwrap_or_default();
+
+    // fallback: if EXIF didn't provide dimensions, read them from the image headers
+    let needs_dims = options.metadata_level >= MetadataLevel::Dimensions
+        && exif.as_ref().is_none_or(|e| e.width.is_none());
+    if needs_dims {
+        exif = with_dimensions(exif, file_dimensions(file_path));
+    }
+    let place = cached_place_label(exif.as_ref());
+
+    let name = file_path
+        .file_name()
+        .ok_or_else(|| ScanError::new(file_path, ScanErrorKind::MetadataError, "no file name"))?;
+    let info = ImageInfo {
+        path: path_str,
+        name: name.to_string_lossy().to_string(),
+        size,
+        created_at,
+        modified_at: mtime,
+        phash: hashes.phash,
+        ahash: hashes.ahash,
+        sha256,
+        exif,
+        phash_rotations: hashes.phash_rotations,
+        phash_mirror: hashes.phash_mirror,
+        hash_algo: options.scan_hash_algo(),
+        hashes_computed: options.compute_hashes,
+        tile_hashes: hashes.tile_hashes,
+        metadata,
+        place,
+        metadata_level: options.metadata_level,
+    };
+
+    Ok(ProcessedImage {
+        info,
+        from_cache: false,
+        decode_error,
+    })
+}
+
+// what a scan's workers are handed: a file on its own, or every entry listed from one zip
+// archive as (archive_entry_path, entry name, size)
+enum ScanUnit {
+    File(std::path::PathBuf, u64),
+    Archive {
+        archive: std::path::PathBuf,
+        entries: Vec<(std::path::PathBuf, String, u64)>,
+    },
+}
+
+// groups sorted scan paths into ScanUnits. an archive's entries sort next to each other,
+// so each archive becomes a single unit
+fn scan_units(paths: Vec<(std::path::PathBuf, u64)>) -> Vec<ScanUnit> {
+    let mut units: Vec<ScanUnit> = Vec::new();
+    for (path, size) in paths {
+        let Some((archive, entry)) = archive_entry(&path) else {
+            units.push(ScanUnit::File(path, size));
+            continue;
+        };
+        match units.last_mut() {
+            Some(ScanUnit::Archive {
+                archive: last,
+                entries,
+            }) if *last == archive => entries.push((path, entry, size)),
+            _ => units.push(ScanUnit::Archive {
+                archive,
+                entries: vec![(path, entry, size)],
+            }),
+        }
+    }
+    units
+}
+
+// archive.zip!/dir/photo.jpg -> (archive.zip, "dir/photo.jpg"). None for a path that
+// isn't inside a zip archive on disk
+fn archive_entry(path: &Path) -> Option<(std::path::PathBuf, String)> {
+    let s = path.to_str()?;
+    let end = s.to_ascii_lowercase().find(".zip!/")? + ".zip".len();
+    let archive = Path::new(&s[..end]);
+    archive
+        .is_file()
+        .then(|| (archive.to_path_buf(), s[end + 2..].to_string()))
+}
+
+fn archive_entry_path(archive: &Path, entry: &str) -> std::path::PathBuf {
+    std::path::PathBuf::from(format!("{}!/{}", archive.display(), entry))
+}
+
+// for commands that need a real file: an archive entry can't be opened, deleted or
+// rewritten in place
+fn reject_archive_entry(path: &str) -> Result<(), String> {
+    match archive_entry(Path::new(path)) {
+        Some((archive, _)) => Err(format!(
+            "{} is inside the archive {}, extract it first",
+            path,
+            archive.display()
+        )),
+        None => Ok(()),
+    }
+}
+
+// whether a cached path is still there: a file on disk, or an entry still listed in a
+// zip that's still there. archives are listed once into `listings`, and one that can't
+// be read keeps its rows rather than look emptied
+fn still_exists(
+    path: &str,
+    listings: &mut std::collections::HashMap<
+        std::path::PathBuf,
+        Option<std::collections::HashSet<String>>,
+    >,
+) -> bool {
+    let Some((archive, entry)) = archive_entry(Path::new(path)) else {
+        return Path::new(path).exists();
+    };
+    let names = listings.entry(archive).or_insert_with_key(|archive| {
+        open_archive(archive)
+            .ok()
+            .map(|zip| zip.file_names().map(str::to_string).collect())
+    });
+    names.as_ref().is_none_or(|names| names.contains(&entry))
+}
+
+type ZipReader = zip::ZipArchive<std::io::BufReader<fs::File>>;
+
+fn open_archive(path: &Path) -> Result<ZipReader, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    zip::ZipArchive::new(std::io::BufReader::new(file)).map_err(|e| e.to_string())
+}
+
+// an archive opened once for all the entries processed from it, with the times its
+// entries are cached under
+struct OpenArchive {
+    zip: ZipReader,
+    mtime: i64,
+    created_at: i64,
+}
+
+impl OpenArchive {
+    fn open(path: &Path) -> Result<Self, String> {
+        let meta = fs::metadata(path).map_err(|e| e.to_string())?;
+        let modified = meta.modified().map_err(|e| e.to_string())?;
+        Ok(OpenArchive {
+            zip: open_archive(path)?,
+            mtime: system_time_to_unix(modified),
+            created_at: system_time_to_unix(meta.created().unwrap_or(modified)),
+        })
+    }
+}
+
+// the image entries of a zip archive as archive_entry_path paths, with their inflated
+// sizes. archives nested inside it aren't opened
+fn list_archive_images(
+    archive: &Path,
+    options: &ScanOptions,
+    exts: &[String],
+) -> Result<Vec<(std::path::PathBuf, u64)>, String> {
+    let mut zip = open_archive(archive)?;
+    let mut found = Vec::new();
+    for i in 0..zip.len() {
+        let entry = zip.by_index_raw(i).map_err(|e| e.to_string())?;
+        // macOS zips carry a __MACOSX folder of ._ resource forks, caught by the dot rule
+        let hidden = !options.include_hidden && entry.name().split('/').any(is_hidden_name);
+        if entry.is_file() && !hidden && has_extension(Path::new(entry.name()), exts) {
+            found.push((archive_entry_path(archive, entry.name()), entry.size()));
+        }
+    }
+    Ok(found)
+}
+
+fn read_archive_entry(zip: &mut ZipReader, name: &str) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    let entry = zip.by_name(name).map_err(|e| e.to_string())?;
+    // the size in the header is the archive's word for it, a zip bomb can lie
+    let mut bytes = Vec::new();
+    entry
+        .take(MAX_ARCHIVE_ENTRY_BYTES + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+    if bytes.len() as u64 > MAX_ARCHIVE_ENTRY_BYTES {
+        return Err(format!(
+            "inflates past the {} MB limit",
+            MAX_ARCHIVE_ENTRY_BYTES / 1024 / 1024
+        ));
+    }
+    Ok(bytes)
+}
+
+// a file's bytes, or an archive entry's read out of its zip
+fn read_file_or_entry(path: &str) -> Result<Vec<u8>, String> {
+    match archive_entry(Path::new(path)) {
+        Some((archive, entry)) => read_archive_entry(&mut open_archive(&archive)?, &entry),
+        None => fs::read(path).map_err(|e| e.to_string()),
+    }
+}
+
+// process_image_file for an image inside a zip archive. entries are cached under the
+// archive's mtime and their size from the zip directory, so the cache is checked before
+// anything is inflated and any change to the archive rescans all of its entries. there's
+// no sidecar to look for, only embedded XMP/IPTC
+fn process_archive_entry(
+    file_path: &Path,
+    archive: &mut OpenArchive,
+    entry: &str,
+    options: &ScanOptions,
+) -> Result<ProcessedImage, ScanError> {
+    let (mtime, created_at) = (archive.mtime, archive.created_at);
+    let path_str = file_path.to_string_lossy().to_string();
+
+    let listed_size = archive
+        .zip
+        .by_name(entry)
+        .map(|e| e.size())
+        .map_err(|e| ScanError::new(file_path, ScanErrorKind::IoError, e))?;
+    if let Some(cached) = cache_get(&path_str, mtime, listed_size).filter(|cached| {
+        cached.metadata_level >= options.metadata_level && cached_hashes_ok(cached, options)
+    }) {
+        let dims = cached
+            .exif
+            .as_ref()
+            .and_then(|e| Some((e.width?, e.height?)));
+        if let Some(dims) = dims {
+            check_min_size(file_path, dims, options)?;
+        }
+        return Ok(ProcessedImage {
+            info: cached,
+            from_cache: true,
+            decode_error: None,
+        });
+    }
+
+    let bytes = read_archive_entry(&mut archive.zip, entry)
+        .map_err(|e| ScanError::new(file_path, ScanErrorKind::IoError, e))?;
+    let size = bytes.len() as u64;
+    let dims = bytes_dimensions(file_path, &bytes);
+    if let Some(dims) = dims {
+        check_min_size(file_path, dims, options)?;
+    }
+
+    let sha256 = options
+        .compute_hashes
+        .then(|| hex::encode(Sha256::digest(&bytes)));
+    let full = options.metadata_level == MetadataLevel::Full;
+    let mut exif = if full { compute_exif(&bytes) } else { None };
+    let metadata = if full {
+        let head = &bytes[..bytes.len().min(METADATA_SCAN_BYTES as usize)];
+        Some(embedded_metadata(head)).filter(|m| !m.is_empty())
+    } else {
+        None
+    };
+    let (img, decode_error) = if options.compute_hashes {
+        let orientation = exif.as_ref().and_then(|e| e.orientation);
+        let decoded = dims
+            .ok_or_else(|| unmeasurable_reason(&bytes))
+            .and_then(|dims| check_pixel_limit(dims, options.max_pixels))
+            .and_then(|_| {
+                load_oriented(
+                    decodable_bytes(file_path, &bytes)?,
+                    orientation,
+                    decode_limits(options.max_pixels),
+                )
+            });
+        match decoded {
+            Ok(img) => (Some(img), None),
+            Err(e) => (
+                None,
+                Some(ScanError::new(file_path, ScanErrorKind::DecodeError, e)),
+            ),
+        }
+    } else {
+        (None, None)
+    };
+    let hashes = img
+        .as_ref()
+        .map(|i| pixel_hashes(i, options))
+        .unwrap_or_default();
+    if options.metadata_level >= MetadataLevel::Dimensions
+        && exif.as_ref().is_none_or(|e| e.width.is_none())
+    {
+        exif = with_dimensions(exif, dims);
+    }
+    let place = cached_place_label(exif.as_ref());
+
+    let name = Path::new(entry)
+        .file_name()
+        .ok_or_else(|| ScanError::new(file_path, ScanErrorKind::MetadataError, "no file name"))?;
+    let info = ImageInfo {
+        path: path_str,
+        name: name.to_string_lossy().to_string(),
+        size,
+        created_at,
+        modified_at: mtime,
+        phash: hashes.phash,
+        ahash: hashes.ahash,
+        sha256,
+        exif,
+        phash_rotations: hashes.phash_rotations,
+        phash_mirror: hashes.phash_mirror,
+        hash_algo: options.scan_hash_algo(),
+        hashes_computed: options.compute_hashes,
+        tile_hashes: hashes.tile_hashes,
+        metadata,
+        place,
+        metadata_level: options.metadata_level,
+    };
+
+    Ok(ProcessedImage {
+        info,
+        from_cache: false,
+        decode_error,
+    })
+}
+
+// inverse of phash_hex, for hashes coming back from the frontend or pre-v5 caches
+fn parse_phash(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex, 16).ok()
+}
+
+// hamming distance between two 64-bit hashes
+fn phash_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// BK-tree over 64-bit dHash values, keyed on hamming distance. lets us find all
+// neighbours within a threshold without comparing every pair of images
+#[derive(Serialize, Deserialize)]
+struct BkNode {
+    hash: u64,
+    items: Vec<usize>,           // indices of every image with exactly this hash
+    children: Vec<(u32, usize)>, // (distance to this node, child node index)
+}
+
+#[derive(Serialize, Deserialize)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+impl BkTree {
+    fn new() -> Self {
+        BkTree { nodes: Vec::new() }
+    }
+
+    fn insert(&mut self, hash: u64, item: usize) {
+        if self.nodes.is_empty() {
+            self.nodes.push(BkNode {
+                hash,
+                items: vec![item],
+                children: Vec::new(),
+            });
+            return;
+        }
+
+        let mut cur = 0;
+        loop {
+            let d = phash_distance(self.nodes[cur].hash, hash);
+            if d == 0 {
+                self.nodes[cur].items.push(item);
+                return;
+            }
+            match self.nodes[cur].children.iter().find(|(cd, _)| *cd == d) {
+                Some(&(_, child)) => cur = child,
+                None => {
+                    let idx = self.nodes.len();
+                    self.nodes.push(BkNode {
+                        hash,
+                        items: vec![item],
+                        children: Vec::new(),
+                    });
+                    self.nodes[cur].children.push((d, idx));
+                    return;
+                }
+            }
+        }
+    }
+
+    // every item whose hash is within `threshold` of `hash`
+    fn find_within(&self, hash: u64, threshold: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        if self.nodes.is_empty() {
+            return found;
+        }
+
+        let mut stack = vec![0];
+        while let Some(cur) = stack.pop() {
+            let node = &self.nodes[cur];
+            let d = phash_distance(node.hash, hash);
+            if d <= threshold {
+                found.extend_from_slice(&node.items);
+            }
+            // triangle inequality: only subtrees at distance d±threshold can contain matches
+            let lo = d.saturating_sub(threshold);
+            let hi = d + threshold;
+            for &(cd, child) in &node.children {
+                if cd >= lo && cd <= hi {
+                    stack.push(child);
+                }
+            }
+        }
+        found
+    }
+}
+
+fn color_signature(img: &image::DynamicImage) -> ColorSignature {
+    let small = img
+        .thumbnail(COLOR_SAMPLE_EDGE, COLOR_SAMPLE_EDGE)
+        .to_rgb8();
+    let bin = |c: u8| c as usize * COLOR_BINS / 256;
+    let mut counts = vec![0u32; COLOR_BINS.pow(3)];
+    let mut sums = [0u64; 3];
+    for p in small.pixels() {
+        counts[(bin(p[0]) * COLOR_BINS + bin(p[1])) * COLOR_BINS + bin(p[2])] += 1;
+        for (sum, &c) in sums.iter_mut().zip(p.0.iter()) {
+            *sum += c as u64;
+        }
+    }
+    let n = (small.width() as u64 * small.height() as u64).max(1);
+    ColorSignature {
+        average: sums.map(|sum| (sum / n) as u8),
+        histogram: counts.iter().map(|&c| c as f32 / n as f32).collect(),
+    }
+}
+
+// half the L1 distance between histograms: 0 for the same palette, 1 for disjoint ones
+fn histogram_distance(a: &[f32], b: &[f32]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).abs() as f64)
+        .sum::<f64>()
+        / 2.0
+}
+
+// cached signature for path, computing and storing it if it's missing or stale
+fn color_signature_for(path: &str) -> Result<ColorSignature, String> {
+    let meta = fs::metadata(path).map_err(|e| e.to_string())?;
+    let mtime = system_time_to_unix(meta.modified().map_err(|e| e.to_string())?);
+    if let Some(cached) = color_cache_get(path, mtime) {
+        return Ok(cached);
+    }
+    // orientation doesn't change the colors, so it isn't applied
+    let signature = color_signature(&load_oriented_file(
+        Path::new(path),
+        None,
+        image::Limits::default(),
+    )?);
+    if let Err(e) = color_cache_set(path, mtime, &signature) {
+        eprintln!("Color cache write error for {}: {}", path, e);
+    }
+    Ok(signature)
+}
+
+// union-find with path compression and union by size
+struct DisjointSet {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(n: usize) -> Self {
+        DisjointSet {
+            parent: (0..n).collect(),
+            size: vec![1; n],
+        }
+    }
+
+    fn find(&mut self, x: usize) -> usize {
+        let mut root = x;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut cur = x;
+        while self.parent[cur] != root {
+            let next = self.parent[cur];
+            self.parent[cur] = root;
+            cur = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (ra, rb) = (self.find(a), self.find(b));
+        if ra == rb {
+            return;
+        }
+        let (big, small) = if self.size[ra] >= self.size[rb] {
+            (ra, rb)
+        } else {
+            (rb, ra)
+        };
+        self.parent[small] = big;
+        self.size[big] += self.size[small];
+    }
+
+    // components with more than one member, ordered by their first member's index
+    fn groups(&mut self) -> Vec<Vec<usize>> {
+        let mut by_root: std::collections::HashMap<usize, usize> = std::collections::HashMap::new();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for i in 0..self.parent.len() {
+            let root = self.find(i);
+            let slot = *by_root.entry(root).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[slot].push(i);
+        }
+        groups.retain(|g| g.len() > 1);
+        groups
+    }
+}
+
+fn is_hidden_name(name: &str) -> bool {
+    name.starts_with('.') || SYSTEM_DIRS.contains(&name.to_lowercase().as_str())
+}
+
+fn is_hidden(path: &Path) -> bool {
+    if path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(is_hidden_name)
+    {
+        return true;
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if fs::symlink_metadata(path)
+            .is_ok_and(|m| m.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+        {
+            return true;
+        }
+    }
+    false
+}
+
+// tauri commands
+
+// lowercase extensions (no dot) a scan with these options picks up: its own list if it
+// has one, else the library's
+fn scan_extensions(options: &ScanOptions) -> Vec<String> {
+    match options.extensions {
+        Some(ref exts) => exts.iter().map(|e| e.to_lowercase()).collect(),
+        None => get_extensions(),
+    }
+}
+
+// extensions scans pick up unless told otherwise. IMAGE_EXTS until set_extensions is called
+#[tauri::command]
+fn get_extensions() -> Vec<String> {
+    setting(SETTING_EXTENSIONS)
+        .unwrap_or_else(|| IMAGE_EXTS.iter().map(|e| e.to_string()).collect())
+}
+
+// replaces the library's extension list, kept across sessions. each one is as it appears
+// after the dot, in lowercase letters and digits, e.g. "jpg" or "raf". formats the image
+// crate can't decode are still listed, with their EXIF and SHA-256 but no pHash
+#[tauri::command]
+fn set_extensions(extensions: Vec<String>) -> Result<Vec<String>, String> {
+    if let Some(bad) = extensions.iter().find(|e| {
+        e.is_empty()
+            || e.len() > 10
+            || !e
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+    }) {
+        return Err(format!(
+            "Invalid extension {:?}: use lowercase letters and digits, without the dot",
+            bad
+        ));
+    }
+    let mut extensions = extensions;
+    extensions.sort();
+    extensions.dedup();
+    if extensions.is_empty() {
+        return Err("At least one extension is needed".to_string());
+    }
+    set_setting(
+        SETTING_EXTENSIONS.to_string(),
+        serde_json::json!(extensions),
+    )?;
+    Ok(extensions)
+}
+
+fn has_extension(path: &Path, exts: &[String]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| exts.contains(&ext.to_lowercase()))
+        .unwrap_or(false)
+}
+
+// compiles options.exclude, failing on the first invalid pattern
+fn exclude_matcher(options: &ScanOptions) -> Result<globset::GlobSet, String> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in &options.exclude {
+        let glob = globset::GlobBuilder::new(pattern)
+            .case_insensitive(cfg!(target_os = "windows"))
+            .literal_separator(true)
+            .build()
+            .map_err(|e| format!("Invalid exclude pattern {}: {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+// true if path, relative to root, matches an exclude pattern. root itself never does
+fn is_excluded(root: &Path, path: &Path, exclude: &globset::GlobSet) -> bool {
+    path.strip_prefix(root)
+        .is_ok_and(|rel| !rel.as_os_str().is_empty() && exclude.is_match(rel))
+}
+
+// every file under folder_path the scan options select. unreadable directory entries
+// are passed to on_error rather than aborting the walk
+fn collect_image_paths(
+    folder_path: &str,
+    recursive: bool,
+    options: &ScanOptions,
+    exclude: &globset::GlobSet,
+    deadline: Option<std::time::Instant>,
+    mut on_error: impl FnMut(ScanError),
+) -> Vec<(std::path::PathBuf, u64)> {
+    let walker = WalkDir::new(folder_path).follow_links(options.follow_links);
+    let walker = match options.max_depth {
+        Some(depth) => walker.max_depth(depth),
+        None if recursive => walker,
+        None => walker.max_depth(1),
+    };
+
+    let exts = scan_extensions(options);
+
+    // canonical folders already walked. walkdir only catches links back to an ancestor,
+    // this also stops two links to the same folder from scanning it twice
+    let mut visited: std::collections::HashSet<std::path::PathBuf> =
+        std::collections::HashSet::new();
+    let mut paths = Vec::new();
+    let mut entries = walker
+        .into_iter()
+        // depth 0 is the root itself, which is scanned even if it's a dot-folder
+        .filter_entry(|e| {
+            (options.include_hidden || e.depth() == 0 || !is_hidden(e.path()))
+                && !is_excluded(Path::new(folder_path), e.path(), exclude)
+        });
+    while let Some(entry) = entries.next() {
+        if deadline.is_some_and(|d| std::time::Instant::now() >= d) {
+            let reason = "scan deadline reached while listing files";
+            on_error(ScanError::new(
+                Path::new(folder_path),
+                ScanErrorKind::Timeout,
+                reason,
+            ));
+            break;
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                let path = err.path().unwrap_or(Path::new(folder_path));
+                let kind = match err.loop_ancestor() {
+                    Some(_) => ScanErrorKind::SymlinkLoop,
+                    None => ScanErrorKind::IoError,
+                };
+                on_error(ScanError::new(path, kind, &err));
+                continue;
+            }
+        };
+        if options.follow_links && entry.file_type().is_dir() {
+            let canonical = fs::canonicalize(entry.path()).unwrap_or(entry.path().to_path_buf());
+            if !visited.insert(canonical.clone()) {
+                let reason = format!("already scanned as {}", canonical.display());
+                on_error(ScanError::new(
+                    entry.path(),
+                    ScanErrorKind::SymlinkLoop,
+                    reason,
+                ));
+                entries.skip_current_dir();
+            }
+            continue;
+        }
+        if options.scan_archives
+            && entry.file_type().is_file()
+            && has_extension(entry.path(), &["zip".to_string()])
+        {
+            match list_archive_images(entry.path(), options, &exts) {
+                Ok(found) => paths.extend(
+                    found
+                        .into_iter()
+                        .filter(|(p, _)| !is_excluded(Path::new(folder_path), p, exclude)),
+                ),
+                Err(e) => on_error(ScanError::new(entry.path(), ScanErrorKind::IoError, e)),
+            }
+        } else if entry.file_type().is_file() && has_extension(entry.path(), &exts) {
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            paths.push((entry.into_path(), size));
+        }
+    }
+    paths
+}
+
+// errors are streamed to the frontend as they happen and also kept for the final summary
+fn report_scan_error<R: tauri::Runtime>(
+    app: &tauri::AppHandle<R>,
+    errors: &Mutex<Vec<ScanError>>,
+    err: ScanError,
+) {
+    eprintln!("Scan error ({:?}) {}: {}", err.kind, err.path, err.reason);
+    let _ = app.emit("scan-error", &err);
+    errors.lock().unwrap().push(err);
+}
+
+#[tauri::command]
+fn scan_folder(
+    folder_path: String,
+    recursive: bool,
+    options: Option<ScanOptions>,
+    app: tauri::AppHandle,
+) -> Result<Vec<ImageInfo>, String> {
+    scan_roots(&[folder_path], recursive, options.unwrap_or_default(), &app)
+}
+
+// scans several folders as one library: one walk per root, then a single hashing pass
+// and progress count across all of them. options.max_depth limits how deep each goes
+#[tauri::command]
+fn scan_folders(
+    paths: Vec<String>,
+    options: Option<ScanOptions>,
+    app: tauri::AppHandle,
+) -> Result<Vec<ImageInfo>, String> {
+    if paths.is_empty() {
+        return Err("No folders to scan".to_string());
+    }
+    scan_roots(&paths, true, options.unwrap_or_default(), &app)
+}
+
+fn scan_roots<R: tauri::Runtime>(
+    roots: &[String],
+    recursive: bool,
+    options: ScanOptions,
+    app: &tauri::AppHandle<R>,
+) -> Result<Vec<ImageInfo>, String> {
+    println!("Scanning: {} (recursive: {})", roots.join(", "), recursive);
+    SCAN_CANCELLED.store(false, Ordering::SeqCst);
+    let exclude = exclude_matcher(&options)?;
+    // a pool of its own rather than resizing the global one, which other commands share
+    let pool = options
+        .threads
+        .map(|n| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(n.max(1))
+                .build()
+                .map_err(|e| e.to_string())
+        })
+        .transpose()?;
+    SCAN_RUNNING.store(true, Ordering::SeqCst);
+    let deadline = options
+        .deadline_secs
+        .map(|secs| std::time::Instant::now() + std::time::Duration::from_secs(secs));
+
+    let errors: Mutex<Vec<ScanError>> = Mutex::new(Vec::new());
+    let _ = app.emit(
+        "scan-progress",
+        ScanProgress {
+            phase: ScanPhase::Discovering,
+            current: 0,
+            total: 0,
+            bytes_done: 0,
+            bytes_total: 0,
+        },
+    );
+    let mut paths: Vec<(std::path::PathBuf, u64)> = Vec::new();
+    for root in roots {
+        paths.extend(collect_image_paths(
+            root,
+            recursive,
+            &options,
+            &exclude,
+            deadline,
+            |err| report_scan_error(app, &errors, err),
+        ));
+    }
+    // nested or repeated roots would otherwise hash the same files twice
+    paths.sort();
+    paths.dedup_by(|a, b| a.0 == b.0);
+
+    let total = paths.len();
+    let bytes_total: u64 = paths.iter().map(|(_, size)| size).sum();
+    println!("Found {} image files", total);
+    let _ = app.emit(
+        "scan-progress",
+        ScanProgress {
+            phase: ScanPhase::Discovering,
+            current: total,
+            total,
+            bytes_done: 0,
+            bytes_total,
+        },
+    );
+
+    // emit initial event so the frontend knows the total right away
+    let _ = app.emit(
+        "scan-progress",
+        ScanProgress {
+            phase: ScanPhase::Hashing,
+            current: 0,
+            total,
+            bytes_done: 0,
+            bytes_total,
+        },
+    );
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let bytes_counter = std::sync::atomic::AtomicU64::new(0);
+    // images waiting to be streamed to the frontend. each result is pushed exactly once and
+    // the buffer is swapped out under the lock, so no image can be emitted twice
+    let pending: Mutex<Vec<ImageInfo>> = Mutex::new(Vec::with_capacity(SCAN_RESULT_BATCH));
+    // SHA-256 -> paths, for reporting exact duplicates as soon as the second copy is hashed
+    let seen_hashes: Mutex<std::collections::HashMap<String, Vec<String>>> =
+        Mutex::new(std::collections::HashMap::new());
+
+    // rayon can't be stopped from the outside, so once cancelled the remaining items are
+    // drained without touching them
+    let stopped = || {
+        if SCAN_CANCELLED.load(Ordering::Relaxed) {
+            return true;
+        }
+        // past the deadline the scan winds down exactly like a cancel. swap() makes
+        // sure only the first worker to notice reports it
+        if deadline.is_some_and(|d| std::time::Instant::now() >= d) {
+            if !SCAN_CANCELLED.swap(true, Ordering::SeqCst) {
+                let reason = "scan deadline reached";
+                let err = ScanError::new(Path::new(&roots[0]), ScanErrorKind::Timeout, reason);
+                report_scan_error(app, &errors, err);
+            }
+            return true;
+        }
+        false
+    };
+    let finish = |size: u64, processed: Result<ProcessedImage, ScanError>| {
+        let result = match processed {
+            Ok(processed) => Some(processed),
+            Err(err) => {
+                if matches!(err.kind, ScanErrorKind::Timeout)
+                    && options.timeout_policy == TimeoutPolicy::Abort
+                {
+                    SCAN_CANCELLED.store(true, Ordering::SeqCst);
+                }
+                report_scan_error(app, &errors, err);
+                None
+            }
+        };
+        if let Some(ref processed) = result {
+            if let Some(ref err) = processed.decode_error {
+                quarantine(err);
+                report_scan_error(app, &errors, err.clone());
+            }
+            // written as we go, so even a cancelled scan keeps the work it did
+            if !processed.from_cache {
+                cache_queue(processed.info.clone());
+            }
+            let batch = {
+                let mut pending = pending.lock().unwrap();
+                pending.push(processed.info.clone());
+                if pending.len() >= SCAN_RESULT_BATCH {
+                    std::mem::take(&mut *pending)
+                } else {
+                    Vec::new()
+                }
+            };
+            if !batch.is_empty() {
+                let _ = app.emit("scan-result", batch);
+            }
+            if let Some(ref sha256) = processed.info.sha256 {
+                let paths = {
+                    let mut seen = seen_hashes.lock().unwrap();
+                    let paths = seen.entry(sha256.clone()).or_default();
+                    paths.push(processed.info.path.clone());
+                    (paths.len() > 1).then(|| paths.clone())
+                };
+                if let Some(paths) = paths {
+                    let sha256 = sha256.clone();
+                    let _ = app.emit("duplicate-found", DuplicateFound { sha256, paths });
+                }
+            }
+        }
+        let current = counter.fetch_add(1, Ordering::Relaxed) + 1;
+        let bytes_done = bytes_counter.fetch_add(size, Ordering::Relaxed) + size;
+        // throttle events: emit every 10 files and on the last one to avoid flooding the frontend
+        if current.is_multiple_of(10) || current == total {
+            let _ = app.emit(
+                "scan-progress",
+                ScanProgress {
+                    phase: ScanPhase::Hashing,
+                    current,
+                    total,
+                    bytes_done,
+                    bytes_total,
+                },
+            );
+        }
+        result
+    };
+    let units = scan_units(paths);
+    let hash_all = || -> Vec<ProcessedImage> {
+        units
+            .par_iter()
+            .flat_map_iter(|unit| match unit {
+                ScanUnit::File(_, _) if stopped() => Vec::new(),
+                ScanUnit::File(p, size) => finish(*size, process_file(p, &options))
+                    .into_iter()
+                    .collect(),
+                // the archive is opened once and its entries read in order on this worker,
+                // so file_timeout_secs doesn't apply to them
+                ScanUnit::Archive { archive, entries } => {
+                    let mut opened = OpenArchive::open(archive);
+                    let mut results = Vec::new();
+                    for (p, entry, size) in entries {
+                        if stopped() {
+                            break;
+                        }
+                        let processed = match &mut opened {
+                            Ok(opened) => process_archive_entry(p, opened, entry, &options),
+                            Err(e) => Err(ScanError::new(p, ScanErrorKind::IoError, e.clone())),
+                        };
+                        results.extend(finish(*size, processed));
+                    }
+                    results
+                }
+            })
+            .collect()
+    };
+    let results = match &pool {
+        Some(pool) => pool.install(hash_all),
+        None => hash_all(),
+    };
+
+    // flush whatever didn't fill a whole batch
+    let rest = pending.into_inner().unwrap();
+    if !rest.is_empty() {
+        let _ = app.emit("scan-result", rest);
+    }
+
+    let processed = counter.load(Ordering::SeqCst);
+    let _ = app.emit(
+        "scan-progress",
+        ScanProgress {
+            phase: ScanPhase::Finalizing,
+            current: processed,
+            total,
+            bytes_done: bytes_counter.load(Ordering::SeqCst),
+            bytes_total,
+        },
+    );
+
+    let images: Vec<ImageInfo> = results.into_iter().map(|r| r.info).collect();
+    let errors = errors.into_inner().unwrap();
+    let too_small = errors
+        .iter()
+        .filter(|e| matches!(e.kind, ScanErrorKind::TooSmall))
+        .count();
+    if too_small > 0 {
+        println!("Skipped {} images below the minimum size", too_small);
+    }
+    if errors.len() > too_small {
+        println!(
+            "{} files could not be fully processed",
+            errors.len() - too_small
+        );
+    }
+    // fresh rows were queued by the workers, wait for the last of them before pruning
+    if let Err(e) = cache_flush() {
+        eprintln!("Cache write error: {}", e);
+    }
+
+    if SCAN_CANCELLED.load(Ordering::SeqCst) {
+        // the finalizing event above already carried the partial count. skip pruning
+        // since the unprocessed files would look deleted
+        println!(
+            "Scan cancelled: {} of {} images processed",
+            images.len(),
+            total
+        );
+        SCAN_RUNNING.store(false, Ordering::SeqCst);
+        return Ok(images);
+    }
+
+    // clean up cache rows for files under these roots deleted since the last scan
+    let valid_paths: Vec<String> = images.iter().map(|i| i.path.clone()).collect();
+    if let Err(e) = cache_prune(roots, &valid_paths) {
+        eprintln!("Cache prune error: {}", e);
+    }
+
+    println!("Scan complete: {} images processed", images.len());
+    SCAN_RUNNING.store(false, Ordering::SeqCst);
+    Ok(images)
+}
+
+// every hash an image may match on: as-is first, then any rotations and its mirror
+// image. None without a phash
+fn hash_variants(img: &ImageInfo) -> Option<Vec<u64>> {
+    let mut hashes = vec![img.phash?];
+    hashes.extend(img.phash_rotations.iter().flatten());
+    hashes.extend(img.phash_mirror);
+    Some(hashes)
+}
+
+// images that have a phash, paired with hash_variants
+fn hashed_images(images: &[ImageInfo]) -> Vec<(&ImageInfo, Vec<u64>)> {
+    images
+        .iter()
+        .filter_map(|i| Some((i, hash_variants(i)?)))
+        .collect()
+}
+
+// ahash_variants for each of hashed_images
+fn image_ahashes(with_hash: &[(&ImageInfo, Vec<u64>)]) -> Vec<Vec<u64>> {
+    with_hash
+        .iter()
+        .map(|(img, _)| {
+            ahash_variants(
+                img.ahash,
+                img.phash_rotations.is_some(),
+                img.phash_mirror.is_some(),
+            )
+        })
+        .collect()
+}
+
+// share of a's detailed tiles that have a match anywhere among b's tiles. flat tiles are
+// skipped since every patch of blue sky matches every other
+fn tile_match_fraction(a: &[u64], b: &[u64], threshold: u32) -> f64 {
+    let detailed: Vec<u64> = a
+        .iter()
+        .copied()
+        .filter(|h| (TILE_MIN_DETAIL..=PHASH_BITS - TILE_MIN_DETAIL).contains(&h.count_ones()))
+        .collect();
+    if detailed.is_empty() {
+        return 0.0;
+    }
+    let matched = detailed
+        .iter()
+        .filter(|&&h| b.iter().any(|&t| phash_distance(h, t) <= threshold))
+        .count();
+    matched as f64 / detailed.len() as f64
+}
+
+// smallest distance between two images across their variants. checking every variant of
+// each side against the other's upright hash covers every relative rotation. None if
+// either side has no hashes at all
+fn variant_distance(a: &[u64], b: &[u64]) -> Option<u32> {
+    let (a0, b0) = (*a.first()?, *b.first()?);
+    let ab = a.iter().map(|&h| phash_distance(h, b0));
+    let ba = b.iter().map(|&h| phash_distance(a0, h));
+    ab.chain(ba).min()
+}
+
+// distance between two images' perceptual hashes, rotations and mirroring included, or
+// why they aren't comparable. hashes are only ever compared within one algorithm
+fn image_distance(a: &ImageInfo, b: &ImageInfo) -> Result<u32, HashMismatch> {
+    if a.hash_algo != b.hash_algo {
+        return Err(HashMismatch::DifferentAlgorithms(a.hash_algo, b.hash_algo));
+    }
+    let variants = |img: &ImageInfo| hash_variants(img).unwrap_or_default();
+    variant_distance(&variants(a), &variants(b)).ok_or(HashMismatch::Missing)
+}
+
+// groups indices of `hashes` whose members are within threshold of each other and that
+// `confirm` also accepts
+fn group_similar(
+    hashes: &[Vec<u64>],
+    threshold: u32,
+    confirm: &dyn Fn(usize, usize) -> bool,
+) -> Vec<Vec<usize>> {
+    // only upright hashes go in the tree; variants are used as extra query points
+    let mut tree = BkTree::new();
+    for (idx, variants) in hashes.iter().enumerate() {
+        tree.insert(variants[0], idx);
+    }
+
+    // union every pair within threshold, so grouping is the transitive closure and
+    // doesn't depend on the order images came in
+    let mut sets = DisjointSet::new(hashes.len());
+    for (i, variants) in hashes.iter().enumerate() {
+        for hash in variants {
+            for j in tree.find_within(*hash, threshold) {
+                if confirm(i, j) {
+                    sets.union(i, j);
+                }
+            }
+        }
+    }
+
+    sets.groups()
+}
+
+// groups indices of `hashes`, only ever comparing hashes made by the same algorithm.
+// `confirm` gets indices into `hashes`
+fn group_similar_by_algo(
+    hashes: &[(HashAlgo, &[u64])],
+    threshold: u32,
+    confirm: &dyn Fn(usize, usize) -> bool,
+) -> Vec<Vec<usize>> {
+    let mut by_algo: std::collections::BTreeMap<HashAlgo, Vec<usize>> =
+        std::collections::BTreeMap::new();
+    for (i, (algo, _)) in hashes.iter().enumerate() {
+        by_algo.entry(*algo).or_default().push(i);
+    }
+
+    let mut groups = Vec::new();
+    for (&algo, indices) in &by_algo {
+        let hashes: Vec<Vec<u64>> = indices.iter().map(|&i| hashes[i].1.to_vec()).collect();
+        let confirm = |a: usize, b: usize| confirm(indices[a], indices[b]);
+        for group in group_similar(&hashes, threshold_for(algo, threshold), &confirm) {
+            groups.push(group.into_iter().map(|j| indices[j]).collect());
+        }
+    }
+    groups
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexEntry {
+    path: String,
+    algo: HashAlgo,
+    hashes: Vec<u64>, // hash_variants
+    #[serde(default)]
+    ahashes: Vec<u64>, // ahash_variants
+}
+
+// BK-trees over the cached hashes under `folder` (None for the whole library), one per
+// algorithm, kept on disk so changing the threshold only re-queries. built at hash
+// generation `generation` and stale once the DB has moved past it
+#[derive(Serialize, Deserialize)]
+struct SimilarityIndex {
+    generation: i64,
+    folder: Option<String>, // as a folder_prefix
+    entries: Vec<IndexEntry>,
+    trees: std::collections::BTreeMap<HashAlgo, BkTree>,
+}
+
+impl SimilarityIndex {
+    fn build(conn: &Connection, folder: Option<&str>) -> SqlResult<SimilarityIndex> {
+        let prefix = folder.map(folder_prefix);
+        // read first: a write landing in between leaves the index looking stale, never fresh
+        let generation = hash_generation(conn)?;
+        let mut stmt = conn.prepare(
+            "SELECT path, phash, phash_rotations, hash_algo, ahash, phash_mirror FROM images
+             WHERE phash IS NOT NULL AND (?1 IS NULL OR substr(path, 1, length(?1)) = ?1)",
+        )?;
+        let entries: Vec<IndexEntry> = stmt
+            .query_map(params![prefix], |row| {
+                let mut hashes = vec![row.get::<_, i64>(1)? as u64];
+                let rotations: Option<String> = row.get(2)?;
+                let rotations = rotations.and_then(|j| serde_json::from_str::<Vec<u64>>(&j).ok());
+                let rotated = rotations.is_some();
+                hashes.extend(rotations.into_iter().flatten());
+                let mirror = row.get::<_, Option<i64>>(5)?.map(|h| h as u64);
+                hashes.extend(mirror);
+                let algo: Option<String> = row.get(3)?;
+                let ahash = row.get::<_, Option<i64>>(4)?.map(|h| h as u64);
+                Ok(IndexEntry {
+                    path: row.get(0)?,
+                    algo: HashAlgo::from_db(algo.as_deref()),
+                    ahashes: ahash_variants(ahash, rotated, mirror.is_some()),
+                    hashes,
+                })
+            })?
+            .collect::<SqlResult<_>>()?;
+
+        let mut trees = std::collections::BTreeMap::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            trees
+                .entry(entry.algo)
+                .or_insert_with(BkTree::new)
+                .insert(entry.hashes[0], idx);
+        }
+        Ok(SimilarityIndex {
+            generation,
+            folder: prefix,
+            entries,
+            trees,
+        })
+    }
+
+    // whether this can answer a query for `prefix` at the DB's current generation
+    fn covers(&self, prefix: Option<&str>, generation: i64) -> bool {
+        self.generation == generation
+            && match (&self.folder, prefix) {
+                (None, _) => true,
+                (Some(indexed), Some(wanted)) => wanted.starts_with(indexed.as_str()),
+                (Some(_), None) => false,
+            }
+    }
+
+    // groups of entry indices under `prefix`, same rules as group_similar_by_algo
+    fn groups(
+        &self,
+        prefix: Option<&str>,
+        threshold: u32,
+        ahash_threshold: u32,
+    ) -> Vec<Vec<usize>> {
+        let in_scope = |i: usize| prefix.is_none_or(|p| self.entries[i].path.starts_with(p));
+        let mut sets = DisjointSet::new(self.entries.len());
+        for (i, entry) in self.entries.iter().enumerate() {
+            if !in_scope(i) {
+                continue;
+            }
+            let tree = &self.trees[&entry.algo];
+            let threshold = threshold_for(entry.algo, threshold);
+            for hash in &entry.hashes {
+                for j in tree.find_within(*hash, threshold) {
+                    let ahashes = &self.entries[j].ahashes;
+                    if in_scope(j) && ahash_agrees(&entry.ahashes, ahashes, ahash_threshold) {
+                        sets.union(i, j);
+                    }
+                }
+            }
+        }
+        sets.groups()
+    }
+
+    fn load() -> Option<SimilarityIndex> {
+        let bytes = fs::read(SIMILARITY_INDEX_PATH.get()?).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    // written to a temp file and renamed, so a crash can't leave a truncated index
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = SIMILARITY_INDEX_PATH.get() else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("json.tmp");
+        let bytes = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        fs::write(&tmp, bytes)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn hash_generation(conn: &Connection) -> SqlResult<i64> {
+    conn.query_row("SELECT value FROM hash_generation", [], |row| row.get(0))
+}
+
+// runs `f` with an index that covers `folder` and is up to date, loading the saved one
+// or rebuilding it as needed
+fn with_similarity_index<T>(
+    conn: &Connection,
+    folder: Option<&str>,
+    f: impl FnOnce(&SimilarityIndex) -> T,
+) -> Result<T, String> {
+    let prefix = folder.map(folder_prefix);
+    let generation = hash_generation(conn).map_err(|e| e.to_string())?;
+    let mut current = SIMILARITY_INDEX.lock().unwrap();
+    if current.is_none() {
+        *current = SimilarityIndex::load();
+    }
+    let fresh = current
+        .as_ref()
+        .is_some_and(|index| index.covers(prefix.as_deref(), generation));
+    if !fresh {
+        let index = SimilarityIndex::build(conn, folder).map_err(|e| e.to_string())?;
+        if let Err(e) = index.save() {
+            eprintln!("Couldn't save similarity index: {}", e);
+        }
+        *current = Some(index);
+    }
+    Ok(f(current.as_ref().unwrap()))
+}
+
+// rebuilds the persisted similarity index for `folder` (None for the whole library) and
+// returns how many hashed images it holds. queries rebuild it by themselves when stale,
+// this is for doing it up front, e.g. right after a scan
+#[tauri::command]
+fn rebuild_similarity_index(folder: Option<String>) -> Result<usize, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let index = SimilarityIndex::build(&conn, folder.as_deref()).map_err(|e| e.to_string())?;
+    index.save()?;
+    let count = index.entries.len();
+    *SIMILARITY_INDEX.lock().unwrap() = Some(index);
+    Ok(count)
+}
+
+// files that failed to decode on their last scan, optionally only those under folder_path
+#[tauri::command]
+fn get_quarantined(folder_path: Option<String>) -> Result<Vec<QuarantinedFile>, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT path, reason, quarantined_at FROM quarantine
+             WHERE ?1 IS NULL OR substr(path, 1, length(?1)) = ?1
+             ORDER BY path",
+        )
+        .map_err(|e| e.to_string())?;
+    let prefix = folder_path.as_deref().map(folder_prefix);
+    let rows = stmt
+        .query_map(params![prefix], |row| {
+            Ok(QuarantinedFile {
+                path: row.get(0)?,
+                reason: row.get(1)?,
+                quarantined_at: row.get(2)?,
+            })
+        })
+        .map_err(|e| e.to_string())?;
+    rows.collect::<SqlResult<_>>().map_err(|e| e.to_string())
+}
+
+// stats every cached file under `folder` (or the whole library) to find what changed on
+// disk since it was scanned, without the cost of a rescan. with `rehash`, stale files
+// are reprocessed with the options they were hashed with. missing ones are only
+// reported, since an unplugged drive looks the same as a deleted folder
+#[tauri::command]
+fn verify_library(
+    folder: Option<String>,
+    rehash: Option<bool>,
+) -> Result<LibraryVerification, String> {
+    struct CachedFile {
+        path: String,
+        modified_at: i64,
+        size: u64,
+        options: ScanOptions,
+    }
+    let rows: Vec<CachedFile> = {
+        let conn = read_db().map_err(|e| e.to_string())?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT path, modified_at, size, hash_algo, hashes_computed,
+                        phash_rotations IS NOT NULL, tile_hashes IS NOT NULL,
+                        phash_mirror IS NOT NULL, metadata_level
+                 FROM images WHERE ?1 IS NULL OR substr(path, 1, length(?1)) = ?1",
+            )
+            .map_err(|e| e.to_string())?;
+        let prefix = folder.as_deref().map(folder_prefix);
+        stmt.query_map(params![prefix], |row| {
+            let algo: Option<String> = row.get(3)?;
+            let level: Option<String> = row.get(8)?;
+            Ok(CachedFile {
+                path: row.get(0)?,
+                modified_at: row.get(1)?,
+                size: row.get::<_, i64>(2)? as u64,
+                options: ScanOptions {
+                    hash_algo: HashAlgo::from_db(algo.as_deref()),
+                    compute_hashes: row.get(4)?,
+                    rotation_invariant: row.get(5)?,
+                    crop_resistant: row.get(6)?,
+                    mirror_invariant: row.get(7)?,
+                    metadata_level: MetadataLevel::from_db(level.as_deref()),
+                    ..ScanOptions::default()
+                },
+            })
+        })
+        .and_then(|rows| rows.collect())
+        .map_err(|e| e.to_string())?
+    };
+
+    enum FileState {
+        Unchanged,
+        Stale,
+        Missing,
+    }
+    let states: Vec<FileState> = rows
+        .par_iter()
+        .map(|row| {
+            // an archive entry is cached under its archive's mtime, which changes along
+            // with any entry in it. the size on the row is the entry's, not the archive's
+            let (file, size) = match archive_entry(Path::new(&row.path)) {
+                Some((archive, _)) => (archive, None),
+                None => (std::path::PathBuf::from(&row.path), Some(row.size)),
+            };
+            match fs::metadata(&file) {
+                Ok(meta) => {
+                    let mtime = meta.modified().map(system_time_to_unix).ok();
+                    if mtime == Some(row.modified_at) && size.is_none_or(|s| meta.len() == s) {
+                        FileState::Unchanged
+                    } else {
+                        FileState::Stale
+                    }
+                }
+                Err(_) => FileState::Missing,
+            }
+        })
+        .collect();
+
+    let mut result = LibraryVerification::default();
+    let mut stale_rows = Vec::new();
+    for (row, state) in rows.iter().zip(states) {
+        match state {
+            FileState::Unchanged => result.unchanged += 1,
+            FileState::Stale => {
+                result.stale.push(row.path.clone());
+                stale_rows.push(row);
+            }
+            FileState::Missing => result.missing.push(row.path.clone()),
+        }
+    }
+
+    if rehash.unwrap_or(false) && !stale_rows.is_empty() {
+        let fresh: Vec<ImageInfo> = stale_rows
+            .par_iter()
+            .filter_map(
+                |row| match process_file(Path::new(&row.path), &row.options) {
+                    Ok(processed) => {
+                        if let Some(ref err) = processed.decode_error {
+                            quarantine(err);
+                        }
+                        Some(processed.info)
+                    }
+                    Err(err) => {
+                        eprintln!("Couldn't rehash {}: {}", err.path, err.reason);
+                        None
+                    }
+                },
+            )
+            .collect();
+        cache_set_batch(&fresh)?;
+        result.rehashed = fresh.len();
+    }
+    Ok(result)
+}
+
+// summary of the cached rows under folder (or the whole library), all from SQL aggregates
+#[tauri::command]
+fn get_folder_stats(folder: Option<String>) -> Result<FolderStats, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let prefix = folder.as_deref().map(folder_prefix);
+    // with no folder, ?1 is NULL and the filter matches everything
+    let filter = "(?1 IS NULL OR substr(path, 1, length(?1)) = ?1)";
+    let date = SortBy::Date.sql();
+
+    let (total_images, total_bytes, with_exif_date, earliest, latest) = conn
+        .query_row(
+            &format!(
+                "SELECT COUNT(*), COALESCE(SUM(size), 0),
+                        COUNT(json_extract(exif_json, '$.date')),
+                        MIN({date}) / 1000, MAX({date}) / 1000
+                 FROM images WHERE {filter}"
+            ),
+            params![prefix],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)? as u64,
+                    row.get::<_, i64>(1)? as u64,
+                    row.get::<_, i64>(2)? as u64,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )
+        .map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT format, COUNT(*), COALESCE(SUM(size), 0) FROM images WHERE {filter}
+             GROUP BY format ORDER BY COUNT(*) DESC, format"
+        ))
+        .map_err(|e| e.to_string())?;
+    let by_format = stmt
+        .query_map(params![prefix], |row| {
+            Ok(FormatStats {
+                format: row.get(0)?,
+                count: row.get::<_, i64>(1)? as u64,
+                bytes: row.get::<_, i64>(2)? as u64,
+            })
+        })
+        .and_then(|rows| rows.collect::<SqlResult<Vec<_>>>())
+        .map_err(|e| e.to_string())?;
+
+    Ok(FolderStats {
+        total_images,
+        total_bytes,
+        by_format,
+        with_exif_date,
+        without_exif_date: total_images - with_exif_date,
+        earliest,
+        latest,
+    })
+}
+
+// perceptual distance between two images, or an error saying why they can't be compared
+// (no hash, or hashes from different algorithms) rather than a fake "very different"
+#[tauri::command]
+fn compare_images(a: ImageInfo, b: ImageInfo) -> Result<u32, String> {
+    image_distance(&a, &b).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ImageDiff {
+    pub heatmap: String, // JPEG data URL: image a in dim grey, differences in red to yellow
+    pub width: u32,      // resolution the comparison ran at, the larger of the two
+    pub height: u32,
+    pub mse: f64,              // mean squared error over all RGB channels, 0 = identical
+    pub similarity: f64,       // 1 - RMSE/255, so 1.0 = identical
+    pub changed_fraction: f64, // share of pixels differing by more than DIFF_PIXEL_TOLERANCE
+}
+
+// dark grey -> red -> yellow as the difference grows
+fn heat_color(base: u8, diff: u8) -> [u8; 3] {
+    let grey = base / 3;
+    if diff <= DIFF_PIXEL_TOLERANCE {
+        return [grey, grey, grey];
+    }
+    let t = diff as f32 / 255.0;
+    let red = (grey as f32 + (255.0 - grey as f32) * (t * 2.0).min(1.0)) as u8;
+    let green = (grey as f32 * (1.0 - t) + 255.0 * (t * 2.0 - 1.0).max(0.0)) as u8;
+    [red, green, grey / 2]
+}
+
+// pixel-level comparison of two images, for deciding between near-duplicates by eye.
+// the smaller one is scaled up to the larger's size first, so the result shows real
+// edits rather than resampling. images whose aspect ratios differ (crops) are refused,
+// scaling would misalign every pixel
+#[tauri::command]
+fn diff_images(path_a: String, path_b: String) -> Result<ImageDiff, String> {
+    let load = |path: &str| {
+        let path = Path::new(path);
+        let orientation = compute_exif_file(path).and_then(|e| e.orientation);
+        decode_checked(path, orientation, MAX_IMAGE_PIXELS).map(|img| img.to_rgb8())
+    };
+    let (a, b) = rayon::join(|| load(&path_a), || load(&path_b));
+    let (mut a, mut b) = (a?, b?);
+
+    let aspect = |img: &image::RgbImage| img.width() as f64 / img.height().max(1) as f64;
+    if (aspect(&a) / aspect(&b) - 1.0).abs() > DIFF_ASPECT_TOLERANCE {
+        return Err(format!(
+            "The images have different aspect ratios ({}×{} vs {}×{})",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        ));
+    }
+    let (width, height) =
+        if a.width() as u64 * a.height() as u64 >= b.width() as u64 * b.height() as u64 {
+            a.dimensions()
+        } else {
+            b.dimensions()
+        };
+    let filter = image::imageops::FilterType::Triangle;
+    if a.dimensions() != (width, height) {
+        a = image::imageops::resize(&a, width, height, filter);
+    }
+    if b.dimensions() != (width, height) {
+        b = image::imageops::resize(&b, width, height, filter);
+    }
+
+    let mut heatmap = image::RgbImage::new(width, height);
+    let mut squared_sum = 0.0f64;
+    let mut changed = 0u64;
+    for ((pa, pb), out) in a.pixels().zip(b.pixels()).zip(heatmap.pixels_mut()) {
+        let mut worst = 0u8;
+        for c in 0..3 {
+            let d = pa.0[c].abs_diff(pb.0[c]);
+            squared_sum += (d as f64) * (d as f64);
+            worst = worst.max(d);
+        }
+        if worst > DIFF_PIXEL_TOLERANCE {
+            changed += 1;
+        }
+        let luma = ((pa.0[0] as u32 * 3 + pa.0[1] as u32 * 6 + pa.0[2] as u32) / 10) as u8;
+        out.0 = heat_color(luma, worst);
+    }
+
+    let pixels = width as u64 * height as u64;
+    let mse = squared_sum / (pixels * 3).max(1) as f64;
+    // thumbnail() would also scale small images up
+    let preview = if width.max(height) > DIFF_PREVIEW_EDGE {
+        image::DynamicImage::ImageRgb8(heatmap)
+            .thumbnail(DIFF_PREVIEW_EDGE, DIFF_PREVIEW_EDGE)
+            .to_rgb8()
+    } else {
+        heatmap
+    };
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, THUMBNAIL_QUALITY)
+        .encode_image(&preview)
+        .map_err(|e| e.to_string())?;
+
+    Ok(ImageDiff {
+        heatmap: jpeg_data_url(&jpeg),
+        width,
+        height,
+        mse,
+        similarity: 1.0 - mse.sqrt() / 255.0,
+        changed_fraction: changed as f64 / pixels.max(1) as f64,
+    })
+}
+
+// returns what's already in the cache without touching the filesystem, for instant startup
+#[tauri::command]
+fn get_cached_images(folder_path: Option<String>) -> Result<Vec<ImageInfo>, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    cache_list(&conn, folder_path.as_deref()).map_err(|e| e.to_string())
+}
+
+// one page of the cached library, sorted in SQL so the frontend can virtualize the list.
+// path is the tiebreaker so rows with equal keys never shuffle between pages
+#[tauri::command]
+fn get_images_page(
+    folder_path: Option<String>,
+    offset: u64,
+    limit: u64,
+    sort_by: SortBy,
+    descending: bool,
+) -> Result<ImagePage, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let dir = if descending { "DESC" } else { "ASC" };
+    // with no folder, ?1 is NULL and the filter matches everything
+    let filter = "(?1 IS NULL OR substr(path, 1, length(?1)) = ?1)";
+    let prefix = folder_path.as_deref().map(folder_prefix);
+
+    let total: i64 = conn
+        .query_row(
+            &format!("SELECT COUNT(*) FROM images WHERE {filter}"),
+            params![prefix],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {IMAGE_COLUMNS} FROM images WHERE {filter}
+             ORDER BY {} {dir}, path {dir} LIMIT ?2 OFFSET ?3",
+            sort_by.sql()
+        ))
+        .map_err(|e| e.to_string())?;
+    let images = stmt
+        .query_map(params![prefix, limit as i64, offset as i64], row_to_image)
+        .map_err(|e| e.to_string())?
+        .collect::<SqlResult<Vec<_>>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(ImagePage {
+        images,
+        total: total as u64,
+    })
+}
+
+// cached images matching every filter that's set, filtered in SQL on the indexed
+// format/pixels/orientation columns instead of in the frontend
+#[tauri::command]
+fn query_images(query: ImageQuery) -> Result<Vec<ImageInfo>, String> {
+    let mut clauses: Vec<String> = Vec::new();
+    let mut args: Vec<rusqlite::types::Value> = Vec::new();
+
+    if let Some(ref folder) = query.folder {
+        args.push(folder_prefix(folder).into());
+        clauses.push(format!("substr(path, 1, length(?{0})) = ?{0}", args.len()));
+    }
+    if let Some(ref formats) = query.formats {
+        let mut placeholders = Vec::new();
+        for format in formats {
+            args.push(format.to_lowercase().into());
+            placeholders.push(format!("?{}", args.len()));
+        }
+        if placeholders.is_empty() {
+            return Ok(Vec::new());
+        }
+        clauses.push(format!("format IN ({})", placeholders.join(", ")));
+    }
+    if let Some(min) = query.min_pixels {
+        args.push((min as i64).into());
+        clauses.push(format!("pixels >= ?{}", args.len()));
+    }
+    if let Some(max) = query.max_pixels {
+        args.push((max as i64).into());
+        clauses.push(format!("pixels <= ?{}", args.len()));
+    }
+    if let Some(ref orientation) = query.orientation {
+        args.push(orientation.to_lowercase().into());
+        clauses.push(format!("orientation = ?{}", args.len()));
+    }
+    if let Some(favorite) = query.favorite {
+        let op = if favorite { "IN" } else { "NOT IN" };
+        clauses.push(format!(
+            "path {op} (SELECT path FROM ratings WHERE favorite = 1)"
+        ));
+    }
+    if let Some(ref place) = query.place {
+        args.push(place.to_lowercase().into());
+        clauses.push(format!("instr(lower(place), ?{}) > 0", args.len()));
+    }
+
+    let where_sql = if clauses.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", clauses.join(" AND "))
+    };
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {IMAGE_COLUMNS} FROM images {where_sql} ORDER BY path"
+        ))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(rusqlite::params_from_iter(args), row_to_image)
+        .map_err(|e| e.to_string())?;
+    rows.collect::<SqlResult<Vec<_>>>()
+        .map_err(|e| e.to_string())
+}
+
+// processes files in parallel without streaming progress, reporting per-file errors
+fn process_paths(
+    paths: &[std::path::PathBuf],
+    options: &ScanOptions,
+    app: &tauri::AppHandle,
+    errors: &Mutex<Vec<ScanError>>,
+) -> Vec<ProcessedImage> {
+    paths
+        .par_iter()
+        .filter_map(|p| match process_file(p, options) {
+            Ok(processed) => {
+                if let Some(ref err) = processed.decode_error {
+                    quarantine(err);
+                    report_scan_error(app, errors, err.clone());
+                }
+                Some(processed)
+            }
+            Err(err) => {
+                report_scan_error(app, errors, err);
+                None
+            }
+        })
+        .collect()
+}
+
+// scans a whole folder in one go, caching fresh results like scan_folder does
+fn scan_folder_quiet(
+    folder_path: &str,
+    options: &ScanOptions,
+    exclude: &globset::GlobSet,
+    app: &tauri::AppHandle,
+    errors: &Mutex<Vec<ScanError>>,
+) -> Vec<ImageInfo> {
+    let paths: Vec<std::path::PathBuf> =
+        collect_image_paths(folder_path, true, options, exclude, None, |err| {
+            report_scan_error(app, errors, err)
+        })
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    let results = process_paths(&paths, options, app, errors);
+
+    let fresh: Vec<ImageInfo> = results
+        .iter()
+        .filter(|r| !r.from_cache)
+        .map(|r| r.info.clone())
+        .collect();
+    if let Err(e) = cache_set_batch(&fresh) {
+        eprintln!("Cache write error: {}", e);
+    }
+    results.into_iter().map(|r| r.info).collect()
+}
+
+// empty, tiny, or header-less image files under `folder` (recursive), e.g. failed or
+// half-finished downloads, for bulk cleanup. header-only, so it's much faster than a
+// scan — a file cut off after a valid header passes here and ends up quarantined by
+// the next scan that decodes it
+#[tauri::command]
+fn find_broken_images(
+    folder: String,
+    options: Option<ScanOptions>,
+) -> Result<Vec<BrokenImage>, String> {
+    let options = options.unwrap_or_default();
+    let exclude = exclude_matcher(&options)?;
+    let files = collect_image_paths(&folder, true, &options, &exclude, None, |err| {
+        eprintln!("Skipping {}: {}", err.path, err.reason)
+    });
+
+    let mut broken: Vec<BrokenImage> = files
+        .par_iter()
+        .filter_map(|(path, size)| {
+            let reason = if *size == 0 {
+                BrokenReason::Empty
+            } else if *size < MIN_IMAGE_BYTES {
+                BrokenReason::TooSmall
+            } else if file_dimensions(path).is_none()
+                // without the heic or avif feature those dimensions can't be read at
+                // all, so a valid ftyp box is as far as the check goes
+                && !lacks_decoder(path)
+            {
+                BrokenReason::UnreadableHeader
+            } else {
+                return None;
+            };
+            Some(BrokenImage {
+                path: path.to_string_lossy().to_string(),
+                size: *size,
+                reason,
+            })
+        })
+        .collect();
+    broken.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(broken)
+}
+
+// finds files in folder_a that already exist in folder_b, either byte-identical or
+// perceptually similar. both folders are scanned recursively
+#[tauri::command]
+fn compare_two_folders(
+    folder_a: String,
+    folder_b: String,
+    threshold: Option<u32>,
+    options: Option<ScanOptions>,
+    app: tauri::AppHandle,
+) -> Result<Vec<FolderMatch>, String> {
+    println!("Comparing: {} against {}", folder_a, folder_b);
+    let threshold = threshold.unwrap_or_else(default_threshold).min(PHASH_BITS);
+    let options = options.unwrap_or_default();
+    let exclude = exclude_matcher(&options)?;
+
+    let errors: Mutex<Vec<ScanError>> = Mutex::new(Vec::new());
+    let images_a = scan_folder_quiet(&folder_a, &options, &exclude, &app, &errors);
+    let images_b = scan_folder_quiet(&folder_b, &options, &exclude, &app, &errors);
+
+    let mut by_sha: std::collections::HashMap<&str, Vec<&ImageInfo>> =
+        std::collections::HashMap::new();
+    for img in &images_b {
+        if let Some(ref sha256) = img.sha256 {
+            by_sha.entry(sha256).or_default().push(img);
+        }
+    }
+
+    // one tree per algorithm, hashes from different algorithms aren't comparable
+    let hashed_b = hashed_images(&images_b);
+    let mut trees: std::collections::BTreeMap<HashAlgo, BkTree> = std::collections::BTreeMap::new();
+    for (j, (img, variants)) in hashed_b.iter().enumerate() {
+        trees
+            .entry(img.hash_algo)
+            .or_insert_with(BkTree::new)
+            .insert(variants[0], j);
+    }
+
+    let mut matches = Vec::new();
+    for img in &images_a {
+        let exact: Vec<&ImageInfo> = img
+            .sha256
+            .as_deref()
+            .and_then(|sha| by_sha.get(sha))
+            .map(|b| b.iter().copied().filter(|b| b.path != img.path).collect())
+            .unwrap_or_default();
+        for b in &exact {
+            matches.push(FolderMatch {
+                a: img.clone(),
+                b: (*b).clone(),
+                kind: MatchKind::Exact,
+                distance: 0,
+            });
+        }
+
+        let (Some(tree), Some(variants)) = (trees.get(&img.hash_algo), hash_variants(img)) else {
+            continue;
+        };
+        let mut similar: Vec<usize> = variants
+            .iter()
+            .flat_map(|&h| tree.find_within(h, threshold_for(img.hash_algo, threshold)))
+            .collect();
+        similar.sort_unstable();
+        similar.dedup();
+        for j in similar {
+            let (b, b_variants) = &hashed_b[j];
+            if b.path == img.path || exact.iter().any(|e| e.path == b.path) {
+                continue;
+            }
+            let Some(distance) = variant_distance(&variants, b_variants) else {
+                continue;
+            };
+            matches.push(FolderMatch {
+                a: img.clone(),
+                b: (*b).clone(),
+                kind: MatchKind::Similar,
+                distance,
+            });
+        }
+    }
+
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        println!("{} files could not be fully processed", errors.len());
+    }
+    matches.sort_by(|x, y| (&x.a.path, x.distance).cmp(&(&y.a.path, y.distance)));
+    println!("Comparison complete: {} matches", matches.len());
+    Ok(matches)
+}
+
+// files in source_folder whose content isn't anywhere in target_folder, whatever their
+// name or location there, e.g. what a backup is still missing. both folders are scanned
+// recursively, reusing cached hashes for files that haven't changed
+#[tauri::command]
+fn missing_in_target(
+    source_folder: String,
+    target_folder: String,
+    options: Option<ScanOptions>,
+    app: tauri::AppHandle,
+) -> Result<Vec<ImageInfo>, String> {
+    println!(
+        "Checking {} is contained in {}",
+        source_folder, target_folder
+    );
+    // SHA-256 is the whole comparison, so a metadata-only scan can't answer this
+    let options = ScanOptions {
+        compute_hashes: true,
+        ..options.unwrap_or_default()
+    };
+    let exclude = exclude_matcher(&options)?;
+
+    let errors: Mutex<Vec<ScanError>> = Mutex::new(Vec::new());
+    let target = scan_folder_quiet(&target_folder, &options, &exclude, &app, &errors);
+    let in_target: std::collections::HashSet<&str> = target
+        .iter()
+        .filter_map(|img| img.sha256.as_deref())
+        .collect();
+    let missing: Vec<ImageInfo> =
+        scan_folder_quiet(&source_folder, &options, &exclude, &app, &errors)
+            .into_iter()
+            .filter(|img| {
+                img.sha256
+                    .as_deref()
+                    .is_some_and(|sha| !in_target.contains(sha))
+            })
+            .collect();
+
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        println!("{} files could not be fully processed", errors.len());
+    }
+    println!("{} files missing from the target", missing.len());
+    Ok(missing)
+}
+
+// re-processes files touched since the last debounce window and forgets deleted ones.
+// a debounced event doesn't say what happened, so the file's existence decides
+fn handle_watch_events(
+    root: &Path,
+    paths: Vec<std::path::PathBuf>,
+    options: &ScanOptions,
+    exts: &[String],
+    exclude: &globset::GlobSet,
+    app: &tauri::AppHandle,
+) {
+    let mut paths: Vec<std::path::PathBuf> = paths
+        .into_iter()
+        .filter(|p| has_extension(p, exts))
+        // a file under an excluded folder has a relative path with that folder as a prefix,
+        // so every ancestor has to be checked, not just the file
+        .filter(|p| !p.ancestors().any(|a| is_excluded(root, a, exclude)))
+        .filter(|p| {
+            // hidden-ness applies to every component below the root, like the walker's
+            // filter_entry. removed files can't be stat'd, so only names are checked here
+            options.include_hidden
+                || !p.strip_prefix(root).is_ok_and(|rel| {
+                    rel.components()
+                        .any(|c| c.as_os_str().to_str().is_some_and(is_hidden_name))
+                })
+        })
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    let (existing, missing): (Vec<_>, Vec<_>) = paths.into_iter().partition(|p| p.is_file());
+    let removed: Vec<String> = missing
+        .iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect();
+
+    let errors: Mutex<Vec<ScanError>> = Mutex::new(Vec::new());
+    let results = process_paths(&existing, options, app, &errors);
+    let fresh: Vec<ImageInfo> = results
+        .iter()
+        .filter(|r| !r.from_cache)
+        .map(|r| r.info.clone())
+        .collect();
+
+    if let Err(e) = cache_set_batch(&fresh) {
+        eprintln!("Cache write error: {}", e);
+    }
+    if let Err(e) = cache_remove(&removed) {
+        eprintln!("Cache remove error: {}", e);
+    }
+
+    // cache hits didn't change, so only fresh results count as updated
+    if !fresh.is_empty() || !removed.is_empty() {
+        let _ = app.emit(
+            "library-changed",
+            LibraryChange {
+                updated: fresh,
+                removed,
+            },
+        );
+    }
+}
+
+// watches a folder and keeps the cache in sync with it, emitting "library-changed" for
+// each debounced batch of changes. replaces any folder that was being watched before
+#[tauri::command]
+fn watch_folder(
+    folder_path: String,
+    recursive: bool,
+    options: Option<ScanOptions>,
+    app: tauri::AppHandle,
+) -> Result<(), String> {
+    let options = options.unwrap_or_default();
+    let exts = scan_extensions(&options);
+    let exclude = exclude_matcher(&options)?;
+    let root = std::path::PathBuf::from(&folder_path);
+    let handler_root = root.clone();
+
+    let mut debouncer = new_debouncer(
+        std::time::Duration::from_millis(WATCH_DEBOUNCE_MS),
+        move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                let paths = events.into_iter().map(|e| e.path).collect();
+                handle_watch_events(&handler_root, paths, &options, &exts, &exclude, &app);
+            }
+            Err(e) => eprintln!("Watch error: {}", e),
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mode = if recursive {
+        notify::RecursiveMode::Recursive
+    } else {
+        notify::RecursiveMode::NonRecursive
+    };
+    debouncer
+        .watcher()
+        .watch(&root, mode)
+        .map_err(|e| e.to_string())?;
+
+    *WATCHER.lock().unwrap() = Some(debouncer);
+    println!("Watching: {} (recursive: {})", folder_path, recursive);
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_watching() {
+    if WATCHER.lock().unwrap().take().is_some() {
+        println!("Stopped watching");
+    }
+}
+
+#[tauri::command]
+fn cancel_scan() {
+    SCAN_CANCELLED.store(true, Ordering::SeqCst);
+}
+
+// images are grouped when both their phash and their aHash are within threshold, which
+// keeps out pairs that only happen to share edge gradients
+#[tauri::command]
+fn find_similar_duplicates(
+    images: Vec<ImageInfo>,
+    threshold: Option<u32>,
+    ahash_threshold: Option<u32>,
+) -> Vec<Vec<ImageInfo>> {
+    let threshold = threshold.unwrap_or_else(default_threshold).min(PHASH_BITS);
+    let ahash_threshold = ahash_threshold
+        .unwrap_or_else(default_ahash_threshold)
+        .min(PHASH_BITS);
+    let with_hash = hashed_images(&images);
+    let keyed: Vec<(HashAlgo, &[u64])> = with_hash
+        .iter()
+        .map(|(img, hashes)| (img.hash_algo, hashes.as_slice()))
+        .collect();
+    let ahashes = image_ahashes(&with_hash);
+    let confirm = |i: usize, j: usize| ahash_agrees(&ahashes[i], &ahashes[j], ahash_threshold);
+
+    group_similar_by_algo(&keyed, threshold, &confirm)
+        .into_iter()
+        .map(|g| g.into_iter().map(|i| with_hash[i].0.clone()).collect())
+        .collect()
+}
+
+// same grouping as find_similar_duplicates, but each entry also carries the distance to its
+// closest other group member so the UI can surface the most confident matches first
+#[tauri::command]
+fn find_similar_duplicates_scored(
+    images: Vec<ImageInfo>,
+    threshold: Option<u32>,
+    ahash_threshold: Option<u32>,
+) -> Vec<Vec<ScoredImage>> {
+    let threshold = threshold.unwrap_or_else(default_threshold).min(PHASH_BITS);
+    let ahash_threshold = ahash_threshold
+        .unwrap_or_else(default_ahash_threshold)
+        .min(PHASH_BITS);
+    let with_hash = hashed_images(&images);
+    let keyed: Vec<(HashAlgo, &[u64])> = with_hash
+        .iter()
+        .map(|(img, hashes)| (img.hash_algo, hashes.as_slice()))
+        .collect();
+    let ahashes = image_ahashes(&with_hash);
+    let confirm = |i: usize, j: usize| ahash_agrees(&ahashes[i], &ahashes[j], ahash_threshold);
+
+    group_similar_by_algo(&keyed, threshold, &confirm)
+        .into_iter()
+        .map(|g| {
+            g.iter()
+                .map(|&i| {
+                    let closest_distance = g
+                        .iter()
+                        .filter(|&&j| j != i)
+                        .filter_map(|&j| variant_distance(&with_hash[i].1, &with_hash[j].1))
+                        .min()
+                        .unwrap_or(0);
+                    ScoredImage {
+                        image: with_hash[i].0.clone(),
+                        closest_distance,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[tauri::command]
+fn compute_color_signatures(paths: Vec<String>) -> Vec<ColorResult> {
+    paths
+        .par_iter()
+        .map(|path| match color_signature_for(path) {
+            Ok(signature) => ColorResult {
+                path: path.clone(),
+                signature: Some(signature),
+                error: None,
+            },
+            Err(e) => ColorResult {
+                path: path.clone(),
+                signature: None,
+                error: Some(e),
+            },
+        })
+        .collect()
+}
+
+// clusters images with similar color histograms, e.g. all the mostly-blue beach shots.
+// like the pHash groups it's the transitive closure, so a group can drift in color.
+// images that can't be decoded are left out
+#[tauri::command]
+fn group_by_color(images: Vec<ImageInfo>, tolerance: Option<f64>) -> Vec<Vec<ImageInfo>> {
+    let tolerance = tolerance.unwrap_or(COLOR_TOLERANCE);
+    let with_sig: Vec<(&ImageInfo, ColorSignature)> = images
+        .par_iter()
+        .filter_map(|img| Some((img, color_signature_for(&img.path).ok()?)))
+        .collect();
+
+    let mut sets = DisjointSet::new(with_sig.len());
+    for i in 0..with_sig.len() {
+        for j in i + 1..with_sig.len() {
+            if histogram_distance(&with_sig[i].1.histogram, &with_sig[j].1.histogram) <= tolerance {
+                sets.union(i, j);
+            }
+        }
+    }
+
+    sets.groups()
+        .into_iter()
+        .map(|g| g.into_iter().map(|i| with_sig[i].0.clone()).collect())
+        .collect()
+}
+
+// groups images whose tile hashes overlap enough to be crops of one another. much fuzzier
+// than find_similar_duplicates, so min_fraction is worth tuning. only images scanned with
+// crop_resistant take part
+#[tauri::command]
+fn find_cropped_duplicates(
+    images: Vec<ImageInfo>,
+    threshold: Option<u32>,
+    min_fraction: Option<f64>,
+) -> Vec<Vec<ImageInfo>> {
+    let threshold = threshold.unwrap_or_else(default_threshold).min(PHASH_BITS);
+    let min_fraction = min_fraction.unwrap_or(CROP_MATCH_FRACTION);
+    let tiled: Vec<(&ImageInfo, &Vec<u64>)> = images
+        .iter()
+        .filter_map(|i| Some((i, i.tile_hashes.as_ref()?)))
+        .collect();
+
+    // the crop's tiles are all in the original but not the other way round, so a pair
+    // matches if either side is covered well enough
+    let n = tiled.len();
+    let pairs: Vec<(usize, usize)> = (0..n)
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            let tiled = &tiled;
+            (i + 1..n)
+                .filter(move |&j| {
+                    let ((a, a_tiles), (b, b_tiles)) = (tiled[i], tiled[j]);
+                    a.hash_algo == b.hash_algo
+                        && tile_match_fraction(a_tiles, b_tiles, threshold)
+                            .max(tile_match_fraction(b_tiles, a_tiles, threshold))
+                            >= min_fraction
+                })
+                .map(move |j| (i, j))
+        })
+        .collect();
+
+    let mut sets = DisjointSet::new(n);
+    for (i, j) in pairs {
+        sets.union(i, j);
+    }
+    sets.groups()
+        .into_iter()
+        .map(|g| g.into_iter().map(|i| tiled[i].0.clone()).collect())
+        .collect()
+}
+
+// like find_exact_duplicates, but grouped in SQL over the cache for `folder` (or the whole
+// library) so the images don't have to round-trip through IPC. only duplicates are loaded
+#[tauri::command]
+fn find_exact_duplicates_cached(folder: Option<String>) -> Result<Vec<Vec<ImageInfo>>, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    // with no folder, ?1 is NULL and the filter matches everything
+    let filter = "(?1 IS NULL OR substr(path, 1, length(?1)) = ?1)";
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {IMAGE_COLUMNS} FROM images
+             WHERE {filter} AND sha256 IN (
+                 SELECT sha256 FROM images WHERE sha256 IS NOT NULL AND {filter}
+                 GROUP BY sha256 HAVING COUNT(*) > 1
+             )
+             ORDER BY sha256, path"
+        ))
+        .map_err(|e| e.to_string())?;
+    let prefix = folder.as_deref().map(folder_prefix);
+    let rows = stmt
+        .query_map(params![prefix], row_to_image)
+        .and_then(|rows| rows.collect::<SqlResult<Vec<ImageInfo>>>())
+        .map_err(|e| e.to_string())?;
+
+    // rows come sorted by hash, so each group is a consecutive run
+    let mut groups: Vec<Vec<ImageInfo>> = Vec::new();
+    for img in rows {
+        match groups.last_mut() {
+            Some(group) if group[0].sha256 == img.sha256 => group.push(img),
+            _ => groups.push(vec![img]),
+        }
+    }
+    Ok(groups)
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+// one image in an exported report. `group` numbers the duplicate groups from 1
+#[derive(Debug, Serialize)]
+struct ReportRow<'a> {
+    group: usize,
+    path: &'a str,
+    size: u64,
+    width: Option<u32>,
+    height: Option<u32>,
+    date: Option<String>, // RFC 3339, UTC
+    sha256: Option<&'a str>,
+    #[serde(with = "hex_hash")]
+    phash: Option<u64>,
+    hash_algo: &'static str,
+}
+
+const REPORT_CSV_HEADER: &str = "group,path,size,width,height,date,sha256,phash,hash_algo";
+
+// quotes a CSV field when it holds a delimiter, quote or line break (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn report_csv(rows: &[ReportRow]) -> String {
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    let mut out = format!("{REPORT_CSV_HEADER}\n");
+    for row in rows {
+        let fields = [
+            row.group.to_string(),
+            csv_field(row.path),
+            row.size.to_string(),
+            opt(row.width.map(|w| w.to_string())),
+            opt(row.height.map(|h| h.to_string())),
+            opt(row.date.clone()),
+            opt(row.sha256.map(str::to_string)),
+            opt(row.phash.map(phash_hex)),
+            row.hash_algo.to_string(),
+        ];
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+// writes duplicate groups (or any list of images, as a single group) to `dest_path` for
+// use outside the app
+#[tauri::command]
+fn export_report(
+    groups: Vec<Vec<ImageInfo>>,
+    format: ReportFormat,
+    dest_path: String,
+) -> Result<usize, String> {
+    let rows: Vec<ReportRow> = groups
+        .iter()
+        .enumerate()
+        .flat_map(|(i, group)| group.iter().map(move |img| (i + 1, img)))
+        .map(|(group, img)| ReportRow {
+            group,
+            path: &img.path,
+            size: img.size,
+            width: img.exif.as_ref().and_then(|e| e.width),
+            height: img.exif.as_ref().and_then(|e| e.height),
+            date: chrono::DateTime::from_timestamp(
+                img.exif
+                    .as_ref()
+                    .and_then(|e| e.date)
+                    .unwrap_or(img.created_at),
+                0,
+            )
+            .map(|d| d.to_rfc3339()),
+            sha256: img.sha256.as_deref(),
+            phash: img.phash,
+            hash_algo: img.hash_algo.as_str(),
+        })
+        .collect();
+
+    let contents = match format {
+        ReportFormat::Csv => report_csv(&rows),
+        ReportFormat::Json => serde_json::to_string_pretty(&rows).map_err(|e| e.to_string())?,
+    };
+    fs::write(&dest_path, contents).map_err(|e| format!("Couldn't write {}: {}", dest_path, e))?;
+    Ok(rows.len())
+}
+
+// turns what the user typed into an FTS5 query: every word must match, each as a
+// prefix, and quoted so punctuation in it can't be read as query syntax
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+// cached images under `folder` whose path, name, keywords or caption match every word
+// of `query`, best matches first
+#[tauri::command]
+fn search(query: String, folder: Option<String>) -> Result<Vec<ImageInfo>, String> {
+    let Some(fts) = fts_query(&query) else {
+        return Ok(Vec::new());
+    };
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {IMAGE_COLUMNS} FROM images
+             JOIN (SELECT rowid AS hit, rank FROM images_fts WHERE images_fts MATCH ?1)
+                 ON hit = images.rowid
+             WHERE ?2 IS NULL OR substr(path, 1, length(?2)) = ?2
+             ORDER BY rank"
+        ))
+        .map_err(|e| e.to_string())?;
+    let prefix = folder.as_deref().map(folder_prefix);
+    stmt.query_map(params![fts, prefix], row_to_image)
+        .and_then(|rows| rows.collect())
+        .map_err(|e| e.to_string())
+}
+
+// like find_similar_duplicates over the cache for `folder`, grouped with the persisted
+// similarity index. full rows are loaded just for the images that ended up in a group
+#[tauri::command]
+fn find_similar_duplicates_cached(
+    folder: Option<String>,
+    threshold: Option<u32>,
+    ahash_threshold: Option<u32>,
+) -> Result<Vec<Vec<ImageInfo>>, String> {
+    let threshold = threshold.unwrap_or_else(default_threshold).min(PHASH_BITS);
+    let ahash_threshold = ahash_threshold
+        .unwrap_or_else(default_ahash_threshold)
+        .min(PHASH_BITS);
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let prefix = folder.as_deref().map(folder_prefix);
+    let groups: Vec<Vec<String>> = with_similarity_index(&conn, folder.as_deref(), |index| {
+        index
+            .groups(prefix.as_deref(), threshold, ahash_threshold)
+            .into_iter()
+            .map(|g| {
+                g.into_iter()
+                    .map(|i| index.entries[i].path.clone())
+                    .collect()
+            })
+            .collect()
+    })?;
+
+    let mut get = conn
+        .prepare(&format!(
+            "SELECT {IMAGE_COLUMNS} FROM images WHERE path = ?1"
+        ))
+        .map_err(|e| e.to_string())?;
+    groups
+        .into_iter()
+        .map(|g| {
+            g.iter()
+                .map(|path| get.query_row(params![path], row_to_image))
+                .collect::<SqlResult<Vec<ImageInfo>>>()
+        })
+        .collect::<SqlResult<_>>()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn find_exact_duplicates(images: Vec<ImageInfo>) -> Vec<Vec<ImageInfo>> {
+    // group by sha256 hash, anything with more than one entry is a duplicate
+    let mut map: std::collections::HashMap<String, Vec<ImageInfo>> =
+        std::collections::HashMap::new();
+
+    for img in images {
+        if let Some(ref sha256) = img.sha256 {
+            map.entry(sha256.clone()).or_default().push(img);
+        }
+    }
+
+    map.into_values().filter(|g| g.len() > 1).collect()
+}
+
+fn classify_image(img: &ImageInfo) -> ImageKind {
+    let name = img.name.to_lowercase();
+    if SCREENSHOT_NAME_PATTERNS.iter().any(|p| name.contains(p)) {
+        return ImageKind::Screenshot;
+    }
+    let exif = img.exif.as_ref();
+    let from_camera = exif.is_some_and(|e| e.make.is_some() || e.model.is_some());
+    if from_camera {
+        return ImageKind::Photo;
+    }
+    let dims = exif.and_then(|e| Some((e.width?, e.height?)));
+    let screen_sized = dims.is_some_and(|(w, h)| SCREEN_SIZES.contains(&(w.min(h), w.max(h))));
+    // screenshots carry no capture date either, which is what sets them apart from a
+    // photo whose make/model was stripped by an editor
+    if screen_sized && exif.is_none_or(|e| e.date.is_none()) {
+        return ImageKind::Screenshot;
+    }
+    if exif.is_some_and(|e| e.date.is_some()) {
+        return ImageKind::Photo;
+    }
+    ImageKind::Unknown
+}
+
+// sorts images into photos and screenshots from metadata alone: screenshot-style names,
+// camera EXIF, and dimensions that exactly match a common screen
+#[tauri::command]
+fn classify_images(images: Vec<ImageInfo>) -> Vec<ImageClass> {
+    images
+        .iter()
+        .map(|img| ImageClass {
+            path: img.path.clone(),
+            kind: classify_image(img),
+        })
+        .collect()
+}
+
+// scores each image's sharpness for culling blurry shots. images are scored at a fixed
+// size (BLUR_ANALYSIS_EDGE) so the threshold doesn't scale with megapixels, but the
+// absolute value still depends on content — a foggy landscape scores lower than a sharp
+// but busy street. the default of 100 is a reasonable starting point to tune from
+#[tauri::command]
+fn detect_blurry(paths: Vec<String>, threshold: Option<f64>) -> Vec<BlurScore> {
+    let threshold = threshold.unwrap_or(BLUR_THRESHOLD);
+    paths
+        .par_iter()
+        .map(|path| {
+            // orientation doesn't change the variance, so it isn't applied
+            match load_oriented_file(Path::new(path), None, image::Limits::default()) {
+                Ok(img) => {
+                    // only ever shrink, upscaling would smooth the edges being measured
+                    let img = if img.width().max(img.height()) > BLUR_ANALYSIS_EDGE {
+                        img.thumbnail(BLUR_ANALYSIS_EDGE, BLUR_ANALYSIS_EDGE)
+                    } else {
+                        img
+                    };
+                    let sharpness = laplacian_variance(&img);
+                    BlurScore {
+                        path: path.clone(),
+                        sharpness: Some(sharpness),
+                        blurry: sharpness < threshold,
+                        error: None,
+                    }
+                }
+                Err(e) => BlurScore {
+                    path: path.clone(),
+                    sharpness: None,
+                    blurry: false,
+                    error: Some(e),
+                },
+            }
+        })
+        .collect()
+}
+
+// luma histogram of img, 256 bins
+fn luma_histogram(img: &image::DynamicImage) -> [u64; 256] {
+    let mut counts = [0u64; 256];
+    for p in img.to_luma8().pixels() {
+        counts[p[0] as usize] += 1;
+    }
+    counts
+}
+
+fn exposure_stats(path: &str, img: &image::DynamicImage) -> ExposureStats {
+    let counts = luma_histogram(img);
+    let n = counts.iter().sum::<u64>().max(1) as f64;
+    let mean = counts
+        .iter()
+        .enumerate()
+        .map(|(luma, &c)| luma as f64 * c as f64)
+        .sum::<f64>()
+        / n
+        / 255.0;
+    let black = counts[..=CLIPPED_BLACK_LUMA as usize].iter().sum::<u64>() as f64 / n;
+    let white = counts[CLIPPED_WHITE_LUMA as usize..].iter().sum::<u64>() as f64 / n;
+    ExposureStats {
+        path: path.to_string(),
+        mean_luminance: Some(mean),
+        clipped_black: Some(black),
+        clipped_white: Some(white),
+        too_dark: mean < TOO_DARK_MEAN || black > TOO_DARK_CLIPPED,
+        blown_out: mean > BLOWN_OUT_MEAN || white > BLOWN_OUT_CLIPPED,
+        error: None,
+    }
+}
+
+// brightness and clipping per image from its luma histogram, for culling over- and
+// underexposed shots. see TOO_DARK_MEAN for what gets flagged
+#[tauri::command]
+fn analyze_exposure(paths: Vec<String>) -> Vec<ExposureStats> {
+    paths
+        .par_iter()
+        .map(|path| {
+            // orientation doesn't change the histogram, so it isn't applied
+            match load_oriented_file(Path::new(path), None, image::Limits::default()) {
+                Ok(img) => {
+                    let img = if img.width().max(img.height()) > EXPOSURE_ANALYSIS_EDGE {
+                        img.thumbnail(EXPOSURE_ANALYSIS_EDGE, EXPOSURE_ANALYSIS_EDGE)
+                    } else {
+                        img
+                    };
+                    exposure_stats(path, &img)
+                }
+                Err(e) => ExposureStats {
+                    path: path.clone(),
+                    error: Some(e),
+                    ..Default::default()
+                },
+            }
+        })
+        .collect()
+}
+
+// RAW+JPEG shots share a directory and basename. the stem is compared case-insensitively
+// since cameras and import tools don't agree on case
+#[tauri::command]
+fn find_raw_jpeg_pairs(images: Vec<ImageInfo>) -> Vec<RawJpegPair> {
+    let mut map: std::collections::HashMap<
+        (std::path::PathBuf, String),
+        (Vec<ImageInfo>, Vec<ImageInfo>),
+    > = std::collections::HashMap::new();
+
+    for img in images {
+        let path = Path::new(&img.path);
+        let ext = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.to_lowercase(),
+            None => continue,
+        };
+        let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+            continue;
+        };
+        let key = (dir.to_path_buf(), stem.to_string_lossy().to_lowercase());
+        if RAW_EXTS.contains(&ext.as_str()) {
+            map.entry(key).or_default().0.push(img);
+        } else if ext == "jpg" || ext == "jpeg" {
+            map.entry(key).or_default().1.push(img);
+        }
+    }
+
+    let mut pairs: Vec<RawJpegPair> = map
+        .into_values()
+        .flat_map(|(raws, jpegs)| {
+            raws.into_iter().flat_map(move |raw| {
+                jpegs.clone().into_iter().map(move |jpeg| RawJpegPair {
+                    raw: raw.clone(),
+                    jpeg,
+                })
+            })
+        })
+        .collect();
+    pairs.sort_by(|a, b| a.raw.path.cmp(&b.raw.path));
+    pairs
+}
+
+fn pixel_count(img: &ImageInfo) -> u64 {
+    img.exif
+        .as_ref()
+        .and_then(|e| Some(e.width? as u64 * e.height? as u64))
+        .unwrap_or(0)
+}
+
+// capture date when known, otherwise when the file was created
+fn image_date(img: &ImageInfo) -> i64 {
+    img.exif
+        .as_ref()
+        .and_then(|e| e.date)
+        .unwrap_or(img.created_at)
+}
+
+// image_date in milliseconds, so same-second burst frames still sort by SubSecTime
+fn image_date_ms(img: &ImageInfo) -> i64 {
+    img.exif
+        .as_ref()
+        .and_then(|e| e.date_ms.or(e.date.map(|d| d * 1000)))
+        .unwrap_or(img.created_at * 1000)
+}
+
+// runs of shots from the same camera model taken within max_gap_seconds of the previous
+// frame. images without a camera model are left out, there's no way to tell them apart
+#[tauri::command]
+fn find_bursts(images: Vec<ImageInfo>, max_gap_seconds: Option<i64>) -> Vec<Vec<ImageInfo>> {
+    let max_gap = max_gap_seconds.unwrap_or(BURST_MAX_GAP_SECS).max(0);
+
+    let mut by_model: std::collections::HashMap<String, Vec<ImageInfo>> =
+        std::collections::HashMap::new();
+    for img in images {
+        if let Some(model) = img.exif.as_ref().and_then(|e| e.model.clone()) {
+            by_model.entry(model).or_default().push(img);
+        }
+    }
+
+    let mut bursts: Vec<Vec<ImageInfo>> = Vec::new();
+    for (_, mut shots) in by_model {
+        shots.sort_by(|a, b| {
+            image_date_ms(a)
+                .cmp(&image_date_ms(b))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        let mut current: Vec<ImageInfo> = Vec::new();
+        for img in shots {
+            let continues = current
+                .last()
+                .is_some_and(|prev| image_date_ms(&img) - image_date_ms(prev) <= max_gap * 1000);
+            if !continues && current.len() > 1 {
+                bursts.push(std::mem::take(&mut current));
+            } else if !continues {
+                current.clear();
+            }
+            current.push(img);
+        }
+        if current.len() > 1 {
+            bursts.push(current);
+        }
+    }
+
+    bursts.sort_by(|a, b| {
+        image_date_ms(&a[0])
+            .cmp(&image_date_ms(&b[0]))
+            .then_with(|| a[0].path.cmp(&b[0].path))
+    });
+    bursts
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LocationCluster {
+    pub label: String, // most common place among the images, else the centroid's coordinates
+    pub lat: Option<f64>, // centroid, None for the unknown-location bucket
+    pub lon: Option<f64>,
+    pub images: Vec<ImageInfo>,
+}
+
+fn image_coords(img: &ImageInfo) -> Option<(f64, f64)> {
+    let exif = img.exif.as_ref()?;
+    Some((exif.lat?, exif.lon?))
+}
+
+// great-circle distance in km
+fn haversine_km((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (dlat, dlon) = ((lat2 - lat1).to_radians(), (lon2 - lon1).to_radians());
+    let a = (dlat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+// clusters photos taken within radius_km of one another. it's single-linkage like the
+// other groupings, so a walk with a shot every few hundred metres ends up as one
+// cluster. images without GPS go last, in an "Unknown location" bucket
+#[tauri::command]
+fn group_by_location(images: Vec<ImageInfo>, radius_km: Option<f64>) -> Vec<LocationCluster> {
+    let radius = radius_km.unwrap_or(LOCATION_RADIUS_KM).max(0.0);
+    let (located, unknown): (Vec<ImageInfo>, Vec<ImageInfo>) = images
+        .into_iter()
+        .partition(|img| image_coords(img).is_some());
+    let coords: Vec<(f64, f64)> = located.iter().filter_map(image_coords).collect();
+
+    let mut sets = DisjointSet::new(located.len());
+    for i in 0..located.len() {
+        for j in i + 1..located.len() {
+            if haversine_km(coords[i], coords[j]) <= radius {
+                sets.union(i, j);
+            }
+        }
+    }
+
+    // sets.groups() drops singletons, but a lone photo still has a location
+    let mut by_root: std::collections::BTreeMap<usize, Vec<usize>> =
+        std::collections::BTreeMap::new();
+    for i in 0..located.len() {
+        by_root.entry(sets.find(i)).or_default().push(i);
+    }
+
+    let mut clusters: Vec<LocationCluster> = by_root
+        .into_values()
+        .map(|members| {
+            let n = members.len() as f64;
+            let lat = members.iter().map(|&i| coords[i].0).sum::<f64>() / n;
+            let lon = members.iter().map(|&i| coords[i].1).sum::<f64>() / n;
+            let mut counts: std::collections::BTreeMap<&str, usize> =
+                std::collections::BTreeMap::new();
+            for &i in &members {
+                if let Some(ref place) = located[i].place {
+                    *counts.entry(place.as_str()).or_default() += 1;
+                }
+            }
+            // BTreeMap order makes ties pick the alphabetically first place
+            let label = counts
+                .into_iter()
+                .rev()
+                .max_by_key(|&(_, count)| count)
+                .map(|(place, _)| place.to_string())
+                .unwrap_or_else(|| format!("{lat:.4}, {lon:.4}"));
+            let mut images: Vec<ImageInfo> =
+                members.into_iter().map(|i| located[i].clone()).collect();
+            images.sort_by_key(image_date_ms);
+            LocationCluster {
+                label,
+                lat: Some(lat),
+                lon: Some(lon),
+                images,
+            }
+        })
+        .collect();
+
+    // biggest places first
+    clusters.sort_by(|a, b| {
+        b.images
+            .len()
+            .cmp(&a.images.len())
+            .then_with(|| a.label.cmp(&b.label))
+    });
+    if !unknown.is_empty() {
+        clusters.push(LocationCluster {
+            label: "Unknown location".to_string(),
+            lat: None,
+            lon: None,
+            images: unknown,
+        });
+    }
+    clusters
+}
+
+// the image a rule would keep. ties fall through to the lexicographically smallest path so
+// the choice never depends on the order the group came in
+fn pick_keeper(group: &[ImageInfo], rule: KeepRule) -> Option<&ImageInfo> {
+    group.iter().min_by(|a, b| {
+        let preferred = match rule {
+            KeepRule::KeepLargest => b.size.cmp(&a.size),
+            KeepRule::KeepHighestResolution => pixel_count(b)
+                .cmp(&pixel_count(a))
+                .then(b.size.cmp(&a.size)),
+            KeepRule::KeepNewest => image_date_ms(b).cmp(&image_date_ms(a)),
+            KeepRule::KeepOldest => image_date_ms(a).cmp(&image_date_ms(b)),
+            KeepRule::KeepShortestPath => a.path.len().cmp(&b.path.len()),
+        };
+        preferred.then(a.path.cmp(&b.path))
+    })
+}
+
+// per-group disk usage and the image worth keeping: highest resolution, then largest file
+#[tauri::command]
+fn summarize_duplicate_groups(groups: Vec<Vec<ImageInfo>>) -> Vec<GroupSummary> {
+    groups
+        .iter()
+        .filter_map(|group| {
+            let keep = pick_keeper(group, KeepRule::KeepHighestResolution)?;
+            let total_bytes: u64 = group.iter().map(|i| i.size).sum();
+            Some(GroupSummary {
+                count: group.len(),
+                total_bytes,
+                keep_path: keep.path.clone(),
+                reclaimable_bytes: total_bytes - keep.size,
+            })
+        })
+        .collect()
+}
+
+// one-click "select all but the best" — which path to keep and which to delete per group
+#[tauri::command]
+fn auto_select_keepers(groups: Vec<Vec<ImageInfo>>, rule: KeepRule) -> Vec<KeeperSelection> {
+    groups
+        .iter()
+        .filter_map(|group| {
+            let keep = pick_keeper(group, rule)?;
+            Some(KeeperSelection {
+                keep: keep.path.clone(),
+                delete: group
+                    .iter()
+                    .filter(|i| i.path != keep.path)
+                    .map(|i| i.path.clone())
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+// dest_dir/file_name, or "name (1).ext", "name (2).ext", ... if that's taken
+fn unique_destination(
+    dest_dir: &Path,
+    file_name: &std::ffi::OsStr,
+    is_taken: impl Fn(&Path) -> bool,
+) -> std::path::PathBuf {
+    let candidate = dest_dir.join(file_name);
+    if !is_taken(&candidate) {
+        return candidate;
+    }
+    let name = Path::new(file_name);
+    let stem = name.file_stem().unwrap_or(file_name).to_string_lossy();
+    let ext = name
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| dest_dir.join(format!("{} ({}){}", stem, n, ext)))
+        .find(|p| !is_taken(p))
+        .expect("ran out of collision suffixes")
+}
+
+// points every cached row for `from` at `to`. whatever was cached under `to` is stale
+// since nothing exists there yet
+fn rename_cached_path(tx: &rusqlite::Transaction, from: &str, to: &Path) -> SqlResult<()> {
+    let to_str = to.to_string_lossy();
+    let name = to
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    for table in [
+        "images",
+        "thumbnails",
+        "image_tags",
+        "ratings",
+        "color_signatures",
+        "quarantine",
+    ] {
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE path = ?1"),
+            params![to_str],
+        )?;
+        tx.execute(
+            &format!("UPDATE {table} SET path = ?2 WHERE path = ?1"),
+            params![from, to_str],
+        )?;
+    }
+    tx.execute(
+        "UPDATE images SET name = ?2 WHERE path = ?1",
+        params![to_str, name],
+    )?;
+    Ok(())
+}
+
+// points the cache rows for `from` at `to` in one short transaction
+fn rename_in_cache(from: &str, to: &Path) -> Result<(), String> {
+    let mut conn = db().lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    rename_cached_path(&tx, from, to).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+// moves one file and its cache rows together. the file is put in place first, without
+// the DB lock: rename can't cross filesystems, and the copy made instead can take as
+// long as the file is big. the rows are then updated, and the file goes back (or the
+// copy is removed) if that fails. a copy's source is only removed once the rows point
+// at the copy, so there's never a moment with neither
+fn move_with_cache(from: &str, to: &Path) -> Result<(), String> {
+    let source = Path::new(from);
+    let copied = match fs::rename(source, to) {
+        Ok(()) => false,
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_file(source, to).map_err(|e| e.to_string())?;
+            true
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+    if let Err(e) = rename_in_cache(from, to) {
+        let _ = if copied {
+            fs::remove_file(to)
+        } else {
+            fs::rename(to, source)
+        };
+        return Err(e);
+    }
+    if copied {
+        if let Err(e) = fs::remove_file(source) {
+            // don't leave two copies behind, unless the rows can't be pointed back
+            if rename_in_cache(&to.to_string_lossy(), source).is_ok() {
+                let _ = fs::remove_file(to);
+            }
+            return Err(e.to_string());
+        }
+    }
+    Ok(())
+}
+
+// appends op to the undo log, dropping the oldest past OPERATION_LOG_LEN
+fn log_operation(op: FileOperation) {
+    let mut log = OPERATION_LOG.lock().unwrap();
+    if log.len() == OPERATION_LOG_LEN {
+        log.pop_front();
+    }
+    log.push_back(op);
+}
+
+fn log_moves(files: Vec<MovedFile>) {
+    if !files.is_empty() {
+        log_operation(FileOperation::Move { files });
+    }
+}
+
+// reverses the most recent logged operation, or returns None if there's nothing to undo
+#[tauri::command]
+fn undo_last_operation() -> Option<UndoResult> {
+    let op = OPERATION_LOG.lock().unwrap().pop_back()?;
+    let mut restored = 0;
+    let mut errors = Vec::new();
+    match &op {
+        FileOperation::Move { files } => {
+            for file in files.iter().rev() {
+                let original = Path::new(&file.from);
+                // never clobber something that has since taken the old name
+                let result = if original.exists() {
+                    Err(format!("{} already exists", file.from))
+                } else {
+                    original
+                        .parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .map_err(|e| e.to_string())
+                        .and_then(|_| move_with_cache(&file.to, original))
+                };
+                match result {
+                    Ok(()) => restored += 1,
+                    Err(e) => errors.push(format!("{}: {}", file.to, e)),
+                }
+            }
+        }
+    }
+    Some(UndoResult {
+        operation: op,
+        restored,
+        errors,
+    })
+}
+
+// moves files into dest_dir, renaming on collision, and keeps their cache rows, tags and
+// ratings attached to the new path
+#[tauri::command]
+fn move_files(paths: Vec<String>, dest_dir: String) -> Vec<serde_json::Value> {
+    let dest = Path::new(&dest_dir);
+    if let Err(e) = fs::create_dir_all(dest) {
+        return paths
+            .iter()
+            .map(|path| serde_json::json!({ "path": path, "moved": false, "error": e.to_string() }))
+            .collect();
+    }
+    let mut moved = Vec::new();
+    let results = paths
+        .iter()
+        .map(|path| {
+            if let Err(e) = reject_archive_entry(path) {
+                return serde_json::json!({ "path": path, "moved": false, "error": e });
+            }
+            let Some(file_name) = Path::new(path).file_name() else {
+                return serde_json::json!({ "path": path, "moved": false, "error": "not a file path" });
+            };
+            let target = unique_destination(dest, file_name, |p| p.exists());
+            match move_with_cache(path, &target) {
+                Ok(()) => {
+                    moved.push(MovedFile {
+                        from: path.clone(),
+                        to: target.to_string_lossy().to_string(),
+                    });
+                    serde_json::json!({
+                        "path": path,
+                        "moved": true,
+                        "new_path": target.to_string_lossy(),
+                    })
+                }
+                Err(e) => serde_json::json!({ "path": path, "moved": false, "error": e }),
+            }
+        })
+        .collect();
+    log_moves(moved);
+    results
+}
+
+// fs::copy stamps the copy with the current time, so the source's mtime is put back. a
+// copy that can't get it is removed rather than left behind looking newer than it is
+fn copy_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    let modified = fs::metadata(from)?.modified()?;
+    fs::copy(from, to)?;
+    let stamped = fs::OpenOptions::new()
+        .write(true)
+        .open(to)
+        .and_then(|f| f.set_modified(modified));
+    if let Err(e) = stamped {
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// move_files without removing the originals, e.g. for gathering an album. the cache is
+// left alone: the originals haven't changed, and copies inside a scanned folder are
+// picked up as new files by its next scan
+#[tauri::command]
+fn copy_files(paths: Vec<String>, dest_dir: String) -> Vec<serde_json::Value> {
+    let dest = Path::new(&dest_dir);
+    if let Err(e) = fs::create_dir_all(dest) {
+        return paths
+            .iter()
+            .map(
+                |path| serde_json::json!({ "path": path, "copied": false, "error": e.to_string() }),
+            )
+            .collect();
+    }
+    paths
+        .iter()
+        .map(|path| {
+            if let Err(e) = reject_archive_entry(path) {
+                return serde_json::json!({ "path": path, "copied": false, "error": e });
+            }
+            let Some(file_name) = Path::new(path).file_name() else {
+                return serde_json::json!({ "path": path, "copied": false, "error": "not a file path" });
+            };
+            let target = unique_destination(dest, file_name, |p| p.exists());
+            match copy_file(Path::new(path), &target) {
+                Ok(()) => serde_json::json!({
+                    "path": path,
+                    "copied": true,
+                    "new_path": target.to_string_lossy(),
+                }),
+                Err(e) => serde_json::json!({ "path": path, "copied": false, "error": e.to_string() }),
+            }
+        })
+        .collect()
+}
+
+// capture date for a file: the cached EXIF date or created_at, else read fresh from disk
+fn file_date(path: &str) -> Option<i64> {
+    let cached = read_db().ok().and_then(|conn| {
+        conn.query_row(
+            &format!("SELECT {IMAGE_COLUMNS} FROM images WHERE path = ?1"),
+            params![path],
+            row_to_image,
+        )
+        .ok()
+    });
+    if let Some(img) = cached {
+        return Some(image_date(&img));
+    }
+    let exif_date = compute_exif_file(Path::new(path)).and_then(|e| e.date);
+    exif_date.or_else(|| {
+        let meta = fs::metadata(path).ok()?;
+        Some(system_time_to_unix(
+            meta.created().or_else(|_| meta.modified()).ok()?,
+        ))
+    })
+}
+
+// sorts files into dest_root/<template>, e.g. "%Y/%m" -> 2024/07. dates are rendered
+// as stored, which is the camera's wall clock for the many photos with no UTC offset.
+// dry_run returns the plan without touching anything
+#[tauri::command]
+fn organize_by_date(
+    paths: Vec<String>,
+    dest_root: String,
+    template: String,
+    dry_run: bool,
+) -> Result<Vec<PlannedMove>, String> {
+    let items: Vec<chrono::format::Item> = chrono::format::StrftimeItems::new(&template).collect();
+    if items.contains(&chrono::format::Item::Error) {
+        return Err(format!("Invalid date template: {}", template));
+    }
+
+    let root = Path::new(&dest_root);
+    let mut reserved: std::collections::HashSet<std::path::PathBuf> =
+        std::collections::HashSet::new();
+    let mut plan = Vec::new();
+    for path in paths {
+        let subdir = file_date(&path)
+            .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+            .map(|dt| dt.format_with_items(items.iter()).to_string())
+            .unwrap_or_else(|| "undated".to_string());
+        // the template must stay inside dest_root
+        let subdir = Path::new(&subdir);
+        if !subdir
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            return Err(format!(
+                "Date template must produce a relative path: {}",
+                template
+            ));
+        }
+        let Some(file_name) = Path::new(&path).file_name() else {
+            continue;
+        };
+        let dir = root.join(subdir);
+        let target = unique_destination(&dir, file_name, |p| p.exists() || reserved.contains(p));
+        reserved.insert(target.clone());
+        plan.push(PlannedMove {
+            from: path,
+            to: target.to_string_lossy().to_string(),
+            moved: false,
+            error: None,
+        });
+    }
+    if dry_run {
+        return Ok(plan);
+    }
+
+    for item in &mut plan {
+        let target = Path::new(&item.to);
+        let result = target
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .map_err(|e| e.to_string())
+            .and_then(|_| move_with_cache(&item.from, target));
+        match result {
+            Ok(()) => item.moved = true,
+            Err(e) => item.error = Some(e),
+        }
+    }
+    log_moves(
+        plan.iter()
+            .filter(|m| m.moved)
+            .map(|m| MovedFile {
+                from: m.from.clone(),
+                to: m.to.clone(),
+            })
+            .collect(),
+    );
+    Ok(plan)
+}
+
+// expected_sha256 maps a path to the hash it had when scanned. a file whose content
+// has changed since (e.g. re-saved by an editor) is left alone instead of deleted.
+// dry_run runs the same checks and reports what would go, without touching the files
+// or the cache, so a confirmation summary matches what the real delete does
+#[tauri::command]
+fn delete_images(
+    paths: Vec<String>,
+    expected_sha256: Option<std::collections::HashMap<String, String>>,
+    dry_run: Option<bool>,
+) -> Vec<serde_json::Value> {
+    let expected = expected_sha256.unwrap_or_default();
+    let dry_run = dry_run.unwrap_or(false);
     paths
         .iter()
-        .map(|path| match fs::remove_file(path) {
-            Ok(_) => {
+        .map(|path| {
+            reject_archive_entry(path)?;
+            let meta = fs::metadata(path).map_err(|e| e.to_string())?;
+            if !meta.is_file() {
+                return Err("not a file".to_string());
+            }
+            if let Some(hash) = expected.get(path) {
+                match compute_sha256(Path::new(path)) {
+                    Ok(actual) if actual == *hash => {}
+                    Ok(_) => return Err("file content changed since it was scanned".to_string()),
+                    Err(e) => return Err(e.to_string()),
+                }
+            }
+            if !dry_run {
+                fs::remove_file(path).map_err(|e| e.to_string())?;
+            }
+            Ok(meta.len())
+        })
+        .zip(&paths)
+        .map(|(result, path)| match result {
+            Ok(size) if dry_run => {
+                serde_json::json!({ "path": path, "deleted": false, "would_delete": true, "size": size })
+            }
+            Ok(size) => {
                 // also remove from cache so it doesn't show up on next scan
                 if let Ok(conn) = db().lock() {
                     let _ = conn.execute("DELETE FROM images WHERE path = ?1", params![path]);
+                    let _ = conn.execute("DELETE FROM thumbnails WHERE path = ?1", params![path]);
+                    let _ = prune_user_data(&conn);
+                }
+                serde_json::json!({ "path": path, "deleted": true, "size": size })
+            }
+            Err(e) if dry_run => {
+                serde_json::json!({ "path": path, "deleted": false, "would_delete": false, "error": e })
+            }
+            Err(e) => serde_json::json!({ "path": path, "deleted": false, "error": e }),
+        })
+        .collect()
+}
+
+// (device, inode) on unix. elsewhere hard_link itself refuses to cross volumes
+#[cfg(unix)]
+fn file_identity(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_identity(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// links next to `duplicate` first and renames over it, so a failure part way never
+// leaves the path missing
+fn replace_with_hardlink(keeper: &Path, duplicate: &Path) -> std::io::Result<()> {
+    let name = duplicate.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = duplicate.with_file_name(format!(".{}.hardlink-tmp", name));
+    fs::hard_link(keeper, &tmp)?;
+    fs::rename(&tmp, duplicate).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+// replaces each duplicate with a hard link to the keeper, reclaiming the space while
+// every path keeps working. both sides are re-hashed first, and anything on another
+// device or with different content is skipped
+#[tauri::command]
+fn hardlink_duplicates(
+    keeper_path: String,
+    duplicate_paths: Vec<String>,
+) -> Result<Vec<serde_json::Value>, String> {
+    let keeper = Path::new(&keeper_path);
+    let keeper_meta = fs::metadata(keeper).map_err(|e| e.to_string())?;
+    let keeper_hash = compute_sha256(keeper).map_err(|e| e.to_string())?;
+    let keeper_id = file_identity(&keeper_meta);
+
+    let results = duplicate_paths
+        .par_iter()
+        .map(|path| {
+            let dup = Path::new(path);
+            let meta = fs::metadata(dup).map_err(|e| e.to_string())?;
+            if dup == keeper {
+                return Err("this is the keeper".to_string());
+            }
+            match (keeper_id, file_identity(&meta)) {
+                (Some(k), Some(d)) if k == d => return Ok(()), // already linked
+                (Some((k_dev, _)), Some((d_dev, _))) if k_dev != d_dev => {
+                    return Err("on a different device than the keeper".to_string())
+                }
+                _ => {}
+            }
+            if meta.len() != keeper_meta.len()
+                || compute_sha256(dup).map_err(|e| e.to_string())? != keeper_hash
+            {
+                return Err("content differs from the keeper".to_string());
+            }
+            replace_with_hardlink(keeper, dup).map_err(|e| e.to_string())
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .zip(&duplicate_paths)
+        .map(|(result, path)| match result {
+            Ok(()) => serde_json::json!({ "path": path, "linked": true }),
+            Err(e) => serde_json::json!({ "path": path, "linked": false, "error": e }),
+        })
+        .collect();
+    Ok(results)
+}
+
+// the options a cached row was hashed with, so reprocessing it keeps the same hashes
+fn cached_scan_options(img: &ImageInfo) -> ScanOptions {
+    ScanOptions {
+        hash_algo: img.hash_algo,
+        compute_hashes: img.hashes_computed,
+        rotation_invariant: img.phash_rotations.is_some(),
+        crop_resistant: img.tile_hashes.is_some(),
+        mirror_invariant: img.phash_mirror.is_some(),
+        metadata_level: img.metadata_level,
+        ..ScanOptions::default()
+    }
+}
+
+// reprocesses one file after it was edited in place and caches the new row
+fn refresh_cached_file(path: &str) -> Result<ImageInfo, String> {
+    let cached = {
+        let conn = read_db().map_err(|e| e.to_string())?;
+        conn.query_row(
+            &format!("SELECT {IMAGE_COLUMNS} FROM images WHERE path = ?1"),
+            params![path],
+            row_to_image,
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+    };
+    let options = cached.as_ref().map(cached_scan_options).unwrap_or_default();
+    let processed = process_file(Path::new(path), &options).map_err(|e| e.reason)?;
+    if let Some(ref err) = processed.decode_error {
+        quarantine(err);
+    }
+    cache_set_batch(std::slice::from_ref(&processed.info))?;
+    Ok(processed.info)
+}
+
+// every EXIF field in a file, for working out why a date or size didn't come through as
+// expected. read straight from the file, the cache isn't involved. empty if there's no EXIF
+#[tauri::command]
+fn get_exif_raw(path: String) -> Result<Vec<ExifField>, String> {
+    let reader = exif::Reader::new();
+    let exif = match archive_entry(Path::new(&path)) {
+        Some((archive, entry)) => {
+            let bytes = read_archive_entry(&mut open_archive(&archive)?, &entry)?;
+            reader.read_from_container(&mut std::io::Cursor::new(bytes))
+        }
+        None => {
+            let file = fs::File::open(&path).map_err(|e| e.to_string())?;
+            reader.read_from_container(&mut std::io::BufReader::new(file))
+        }
+    };
+    let exif = match exif {
+        Ok(exif) => exif,
+        Err(exif::Error::NotFound(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+    Ok(exif
+        .fields()
+        .map(|f| {
+            let image = match f.ifd_num {
+                In::PRIMARY => "primary".to_string(),
+                In::THUMBNAIL => "thumbnail".to_string(),
+                In(n) => format!("ifd{}", n),
+            };
+            let ifd = match f.tag.context() {
+                exif::Context::Tiff => "tiff",
+                exif::Context::Exif => "exif",
+                exif::Context::Gps => "gps",
+                exif::Context::Interop => "interop",
+                _ => "other",
+            };
+            ExifField {
+                tag_name: f.tag.to_string(),
+                ifd: format!("{}/{}", image, ifd),
+                value_display: f.display_value().with_unit(&exif).to_string(),
+            }
+        })
+        .collect())
+}
+
+// writes `timestamp` into the file's EXIF as the capture date (DateTimeOriginal,
+// DateTimeDigitized and DateTime, in UTC with a +00:00 offset). the edited file is
+// built in memory and only replaces the original once it decodes and the date reads
+// back, so a failed write leaves the image untouched. PNG isn't supported — little_exif
+// stores it in a text chunk that kamadak-exif (and most viewers) never look at
+#[tauri::command]
+fn set_exif_date(path: String, timestamp: i64) -> Result<ImageInfo, String> {
+    use little_exif::exif_tag::ExifTag;
+    use little_exif::filetype::FileExtension;
+
+    if !is_cached_path(&path) {
+        return Err(format!("{} is not in a scanned folder", path));
+    }
+    let file_type = match image_format(&path).as_deref() {
+        Some("jpeg") => FileExtension::JPEG,
+        Some("webp") => FileExtension::WEBP,
+        Some("tiff") => FileExtension::TIFF,
+        _ => return Err("EXIF dates can only be written to JPEG, WebP and TIFF".into()),
+    };
+    let datetime = chrono::DateTime::from_timestamp(timestamp, 0)
+        .ok_or("timestamp out of range")?
+        .format("%Y:%m:%d %H:%M:%S")
+        .to_string();
+
+    let file_path = Path::new(&path);
+    let mut bytes = fs::read(file_path).map_err(|e| e.to_string())?;
+    // little_exif errors on files with no EXIF at all, which is the usual reason to be
+    // here. only start from scratch when kamadak-exif agrees there's nothing to keep
+    let mut metadata = match little_exif::metadata::Metadata::new_from_vec(&bytes, file_type) {
+        Ok(metadata) => metadata,
+        Err(_) if compute_exif(&bytes).is_none() => little_exif::metadata::Metadata::new(),
+        Err(e) => return Err(format!("couldn't read EXIF: {}", e)),
+    };
+    metadata.set_tag(ExifTag::DateTimeOriginal(datetime.clone()));
+    metadata.set_tag(ExifTag::CreateDate(datetime.clone()));
+    metadata.set_tag(ExifTag::ModifyDate(datetime));
+    metadata.set_tag(ExifTag::OffsetTimeOriginal("+00:00".to_string()));
+    metadata.set_tag(ExifTag::OffsetTime("+00:00".to_string()));
+    metadata
+        .write_to_vec(&mut bytes, file_type)
+        .map_err(|e| format!("couldn't write EXIF: {}", e))?;
+
+    // a full decode, the headers alone can look fine on a file that won't open
+    let readable = image::load_from_memory(&bytes).is_ok();
+    if !readable || compute_exif(&bytes).and_then(|e| e.date) != Some(timestamp) {
+        return Err("the edited file didn't verify, the original was left as it was".into());
+    }
+
+    replace_file_contents(file_path, &bytes)?;
+    refresh_cached_file(&path)
+}
+
+// written next to the original and renamed over it, so it's never half-written
+fn replace_file_contents(file_path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = file_path.with_file_name(format!(".{}.edit-tmp", name));
+    let permissions = fs::metadata(file_path)
+        .map_err(|e| e.to_string())?
+        .permissions();
+    let written = fs::File::create(&tmp)
+        .and_then(|mut f| {
+            std::io::Write::write_all(&mut f, bytes)?;
+            f.sync_all()
+        })
+        .and_then(|_| fs::set_permissions(&tmp, permissions))
+        .and_then(|_| fs::rename(&tmp, file_path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.to_string());
+    }
+    Ok(())
+}
+
+fn tiff_u16(b: &[u8], at: usize, le: bool) -> Option<u16> {
+    let s: [u8; 2] = b.get(at..at + 2)?.try_into().ok()?;
+    Some(if le {
+        u16::from_le_bytes(s)
+    } else {
+        u16::from_be_bytes(s)
+    })
+}
+
+fn tiff_u32(b: &[u8], at: usize, le: bool) -> Option<u32> {
+    let s: [u8; 4] = b.get(at..at + 4)?.try_into().ok()?;
+    Some(if le {
+        u32::from_le_bytes(s)
+    } else {
+        u32::from_be_bytes(s)
+    })
+}
+
+// empties the GPS IFD of a TIFF/EXIF block in place: the entry count and entries are
+// zeroed along with any values stored outside them. nothing changes size, so every
+// other offset in the block stays valid. false if there's no GPS IFD
+fn scrub_gps(tiff: &mut [u8]) -> bool {
+    let le = match tiff.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return false,
+    };
+    let Some(ifd0) = tiff_u32(tiff, 4, le).map(|o| o as usize) else {
+        return false;
+    };
+    let entries = tiff_u16(tiff, ifd0, le).unwrap_or(0) as usize;
+    let gps = (0..entries).find_map(|i| {
+        let entry = ifd0 + 2 + i * 12;
+        (tiff_u16(tiff, entry, le)? == 0x8825).then(|| tiff_u32(tiff, entry + 8, le))?
+    });
+    let Some(gps) = gps.map(|o| o as usize) else {
+        return false;
+    };
+    let Some(count) = tiff_u16(tiff, gps, le).map(|n| n as usize) else {
+        return false;
+    };
+    let entries_end = gps + 2 + count * 12;
+    if entries_end > tiff.len() {
+        return false;
+    }
+    for i in 0..count {
+        let entry = gps + 2 + i * 12;
+        let unit = match tiff_u16(tiff, entry + 2, le) {
+            Some(1 | 2 | 6 | 7) => 1,
+            Some(3 | 8) => 2,
+            Some(4 | 9 | 11) => 4,
+            Some(5 | 10 | 12) => 8,
+            _ => continue,
+        };
+        let size = unit * tiff_u32(tiff, entry + 4, le).unwrap_or(0) as usize;
+        // values of four bytes or less live in the entry itself
+        if size > 4 {
+            if let Some(offset) = tiff_u32(tiff, entry + 8, le).map(|o| o as usize) {
+                if let Some(value) = tiff.get_mut(offset..offset.saturating_add(size)) {
+                    value.fill(0);
+                }
+            }
+        }
+    }
+    tiff[gps..entries_end].fill(0);
+    true
+}
+
+// copies the JPEG's segments, leaving out EXIF (APP1), XMP (APP1) and IPTC (APP13).
+// with gps_only the EXIF segment stays but has its GPS IFD scrubbed, and IPTC stays.
+// the compressed image data after SOS is copied byte for byte
+fn strip_jpeg(bytes: &[u8], gps_only: bool) -> Result<Vec<u8>, String> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return Err("not a JPEG file".into());
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..2]);
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() {
+        if bytes[pos] != 0xFF {
+            return Err("corrupt JPEG marker".into());
+        }
+        let marker = bytes[pos + 1];
+        if marker == 0xFF {
+            pos += 1; // fill byte
+            continue;
+        }
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let len = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > bytes.len() {
+            return Err("truncated JPEG segment".into());
+        }
+        let payload = &bytes[pos + 4..end];
+        match marker {
+            0xE1 if payload.starts_with(b"Exif\0\0") => {
+                if gps_only {
+                    let mut segment = bytes[pos..end].to_vec();
+                    scrub_gps(&mut segment[10..]);
+                    out.extend_from_slice(&segment);
+                }
+            }
+            // XMP and extended XMP
+            0xE1 if payload.starts_with(b"http://ns.adobe.com/") => {}
+            0xED if !gps_only => {}
+            _ => out.extend_from_slice(&bytes[pos..end]),
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&bytes[pos..]);
+    Ok(out)
+}
+
+// drops eXIf and the text chunks (where XMP and ImageMagick's hex EXIF live). with
+// gps_only eXIf is scrubbed instead and only the XMP/EXIF text chunks go
+fn strip_png(bytes: &[u8], gps_only: bool) -> Result<Vec<u8>, String> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !bytes.starts_with(SIGNATURE) {
+        return Err("not a PNG file".into());
+    }
+    let mut out = SIGNATURE.to_vec();
+    let mut pos = SIGNATURE.len();
+    while pos + 12 <= bytes.len() {
+        let len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = pos + 12 + len;
+        if end > bytes.len() {
+            return Err("truncated PNG chunk".into());
+        }
+        let kind = &bytes[pos + 4..pos + 8];
+        let data = &bytes[pos + 8..pos + 8 + len];
+        let keyword = data.split(|&b| b == 0).next().unwrap_or_default();
+        let is_text = matches!(kind, b"tEXt" | b"zTXt" | b"iTXt");
+        let embedded = keyword == b"XML:com.adobe.xmp" || keyword.starts_with(b"Raw profile type");
+        match kind {
+            b"eXIf" if gps_only => {
+                let mut data = data.to_vec();
+                scrub_gps(&mut data);
+                let mut crc = crc32fast::Hasher::new();
+                crc.update(kind);
+                crc.update(&data);
+                out.extend_from_slice(&bytes[pos..pos + 8]);
+                out.extend_from_slice(&data);
+                out.extend_from_slice(&crc.finalize().to_be_bytes());
+            }
+            b"eXIf" => {}
+            _ if is_text && (embedded || !gps_only) => {}
+            _ => out.extend_from_slice(&bytes[pos..end]),
+        }
+        pos = end;
+        if kind == b"IEND" {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+// drops the EXIF and XMP chunks and clears their VP8X flags. with gps_only the EXIF
+// chunk is scrubbed instead
+fn strip_webp(bytes: &[u8], gps_only: bool) -> Result<Vec<u8>, String> {
+    const VP8X_XMP: u8 = 0x04;
+    const VP8X_EXIF: u8 = 0x08;
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return Err("not a WebP file".into());
+    }
+    let mut out = bytes[..12].to_vec();
+    let mut vp8x = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let kind = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        if pos + 8 + len > bytes.len() {
+            return Err("truncated WebP chunk".into());
+        }
+        // chunks are padded to an even length
+        let end = (pos + 8 + len + (len & 1)).min(bytes.len());
+        match kind {
+            b"EXIF" if gps_only => {
+                let mut chunk = bytes[pos..end].to_vec();
+                let start = if chunk[8..].starts_with(b"Exif\0\0") {
+                    14
+                } else {
+                    8
+                };
+                scrub_gps(&mut chunk[start..]);
+                out.extend_from_slice(&chunk);
+            }
+            b"EXIF" | b"XMP " => {}
+            _ => {
+                if kind == b"VP8X" {
+                    vp8x = Some(out.len() + 8);
                 }
-                serde_json::json!({ "path": path, "deleted": true })
+                out.extend_from_slice(&bytes[pos..end]);
+            }
+        }
+        pos = end;
+    }
+    if let Some(flags) = vp8x.filter(|&at| at < out.len()) {
+        out[flags] &= !VP8X_XMP;
+        if !gps_only {
+            out[flags] &= !VP8X_EXIF;
+        }
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+// the file's bytes with its metadata removed, or only its GPS position with gps_only.
+// segments are dropped rather than the image re-encoded, so pixels are untouched
+fn strip_metadata_bytes(path: &str, bytes: &[u8], gps_only: bool) -> Result<Vec<u8>, String> {
+    match image_format(path).as_deref() {
+        Some("jpeg") => strip_jpeg(bytes, gps_only),
+        Some("png") => strip_png(bytes, gps_only),
+        Some("webp") => strip_webp(bytes, gps_only),
+        // a TIFF's metadata is part of the image structure, only GPS can be wiped in place
+        Some("tiff") if gps_only => {
+            let mut out = bytes.to_vec();
+            scrub_gps(&mut out);
+            Ok(out)
+        }
+        Some("tiff") => Err("only GPS can be stripped from TIFF files".into()),
+        _ => Err("metadata can only be stripped from JPEG, PNG, WebP and TIFF".into()),
+    }
+}
+
+// removes EXIF, XMP and IPTC from each file before it's shared, or with gps_only just
+// the GPS position, keeping camera and exposure details (embedded XMP goes too, since
+// it can carry a copy of the position). .xmp sidecars are left alone. every result is
+// checked to decode and to have no GPS left before it replaces the original
+#[tauri::command]
+fn strip_metadata(paths: Vec<String>, gps_only: Option<bool>) -> Vec<serde_json::Value> {
+    let gps_only = gps_only.unwrap_or(false);
+    let results: Vec<Result<(), String>> = paths
+        .par_iter()
+        .map(|path| {
+            let bytes = fs::read(path).map_err(|e| e.to_string())?;
+            let stripped = strip_metadata_bytes(path, &bytes, gps_only)?;
+            if image::load_from_memory(&stripped).is_err() {
+                return Err("the stripped file didn't decode, the original was kept".into());
+            }
+            if compute_exif(&stripped).is_some_and(|e| e.lat.is_some() || e.lon.is_some()) {
+                return Err("GPS data survived stripping, the original was kept".into());
+            }
+            if stripped != bytes {
+                replace_file_contents(Path::new(path), &stripped)?;
+            }
+            if is_cached_path(path) {
+                refresh_cached_file(path)?;
+            }
+            Ok(())
+        })
+        .collect();
+
+    results
+        .into_iter()
+        .zip(&paths)
+        .map(|(result, path)| match result {
+            Ok(()) => serde_json::json!({ "path": path, "stripped": true }),
+            Err(e) => serde_json::json!({ "path": path, "stripped": false, "error": e }),
+        })
+        .collect()
+}
+
+// sets each file's modified time to its EXIF capture date, e.g. after a sync tool
+// stamped everything with the download time. files without one are skipped. cached rows
+// that were current follow along, so the next scan doesn't see them as changed
+#[tauri::command]
+fn restore_mtime_from_exif(paths: Vec<String>) -> Vec<serde_json::Value> {
+    let results: Vec<Result<Option<i64>, String>> = paths
+        .par_iter()
+        .map(|path| {
+            let file_path = Path::new(path);
+            let Some(date) = compute_exif_file(file_path).and_then(|e| e.date) else {
+                return Ok(None);
+            };
+            let meta = fs::metadata(file_path).map_err(|e| e.to_string())?;
+            let old_mtime = meta.modified().map(system_time_to_unix).ok();
+            let time = if date >= 0 {
+                UNIX_EPOCH + std::time::Duration::from_secs(date as u64)
+            } else {
+                UNIX_EPOCH - std::time::Duration::from_secs(date.unsigned_abs())
+            };
+            fs::File::options()
+                .write(true)
+                .open(file_path)
+                .and_then(|f| f.set_modified(time))
+                .map_err(|e| e.to_string())?;
+
+            let conn = db().lock().unwrap();
+            conn.execute(
+                "UPDATE images SET modified_at = ?2
+                 WHERE path = ?1 AND modified_at = ?3 AND size = ?4",
+                params![path, date, old_mtime, meta.len() as i64],
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(Some(date))
+        })
+        .collect();
+
+    results
+        .into_iter()
+        .zip(&paths)
+        .map(|(result, path)| match result {
+            Ok(Some(date)) => {
+                serde_json::json!({ "path": path, "restored": true, "modified_at": date })
             }
-            Err(e) => {
-                serde_json::json!({ "path": path, "deleted": false, "error": e.to_string() })
+            Ok(None) => {
+                serde_json::json!({ "path": path, "restored": false, "error": "no EXIF date" })
             }
+            Err(e) => serde_json::json!({ "path": path, "restored": false, "error": e }),
         })
         .collect()
 }
 
+// the full image as a data URL, for when the asset protocol can't be used. this reads and
+// base64-encodes the whole file, so grids should use get_thumbnail instead. RAW files
+// return their embedded JPEG preview since webviews can't display them
+#[tauri::command]
+fn get_image_data(path: String) -> Result<String, String> {
+    if !is_cached_path(&path) {
+        return Err(format!("{} is not in a scanned folder", path));
+    }
+    let bytes = read_file_or_entry(&path)?;
+    let path = Path::new(&path);
+    if is_raw(path) {
+        return Ok(jpeg_data_url(decodable_bytes(path, &bytes)?));
+    }
+    Ok(data_url(mime_type(path), &bytes))
+}
+
+// small JPEG preview as a data URL, so the grid doesn't have to load full-size files.
+// cached per size and invalidated by mtime the same way as the images table
+#[tauri::command]
+fn get_thumbnail(path: String, max_edge: u32) -> Result<String, String> {
+    cached_thumbnail(&path, max_edge.max(1)).map(|thumb| jpeg_data_url(&thumb))
+}
+
+// an archive entry's thumbnail is kept under its archive's mtime, like its cache row
+fn cached_thumbnail(path: &str, max_edge: u32) -> Result<Vec<u8>, String> {
+    let entry = archive_entry(Path::new(path));
+    let file = entry
+        .as_ref()
+        .map_or(Path::new(path), |(archive, _)| archive.as_path());
+    let meta = fs::metadata(file).map_err(|e| e.to_string())?;
+    let mtime = system_time_to_unix(meta.modified().map_err(|e| e.to_string())?);
+
+    if let Some(cached) = thumb_cache_get(path, mtime, max_edge) {
+        return Ok(cached);
+    }
+
+    let bytes = read_file_or_entry(path)?;
+    let thumb = make_thumbnail(Path::new(path), &bytes, max_edge)?;
+    if let Err(e) = thumb_cache_set(path, mtime, max_edge, &thumb) {
+        eprintln!("Thumbnail cache write error for {}: {}", path, e);
+    }
+    Ok(thumb)
+}
+
+// warms the thumbnail cache at `max_edge` for every cached image under `folder` (or the
+// whole library) so the grid never waits on one. runs in the background and reports
+// through "thumbnail-progress" events. returns how many thumbnails are to be made
+#[tauri::command]
+fn pregenerate_thumbnails(
+    folder: Option<String>,
+    max_edge: u32,
+    app: tauri::AppHandle,
+) -> Result<usize, String> {
+    let max_edge = max_edge.max(1);
+    // images already cached at this size, as of their last scan, are skipped up front
+    let paths: Vec<String> = {
+        let conn = read_db().map_err(|e| e.to_string())?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT path FROM images i
+                 WHERE (?1 IS NULL OR substr(path, 1, length(?1)) = ?1)
+                   AND NOT EXISTS (
+                       SELECT 1 FROM thumbnails t
+                       WHERE t.path = i.path AND t.max_edge = ?2
+                         AND t.modified_at = i.modified_at
+                   )
+                 ORDER BY path",
+            )
+            .map_err(|e| e.to_string())?;
+        let prefix = folder.as_deref().map(folder_prefix);
+        stmt.query_map(params![prefix, max_edge], |row| row.get(0))
+            .and_then(|rows| rows.collect::<SqlResult<_>>())
+            .map_err(|e| e.to_string())?
+    };
+
+    let total = paths.len();
+    THUMBNAILS_CANCELLED.store(false, Ordering::SeqCst);
+    std::thread::spawn(move || {
+        let counter = AtomicUsize::new(0);
+        paths.par_iter().for_each(|path| {
+            if THUMBNAILS_CANCELLED.load(Ordering::Relaxed) {
+                return;
+            }
+            if let Err(e) = cached_thumbnail(path, max_edge) {
+                eprintln!("Thumbnail failed for {}: {}", path, e);
+            }
+            let current = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            if current.is_multiple_of(10) {
+                let progress = ThumbnailProgress {
+                    current,
+                    total,
+                    done: false,
+                };
+                let _ = app.emit("thumbnail-progress", progress);
+            }
+        });
+        let progress = ThumbnailProgress {
+            current: counter.load(Ordering::SeqCst),
+            total,
+            done: true,
+        };
+        let _ = app.emit("thumbnail-progress", progress);
+    });
+    Ok(total)
+}
+
+#[tauri::command]
+fn cancel_thumbnails() {
+    THUMBNAILS_CANCELLED.store(true, Ordering::SeqCst);
+}
+
+// DejaVu Sans, see fonts/LICENSE-DejaVu.txt
+static CAPTION_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+
+fn text_width(font: &ab_glyph::PxScaleFont<&ab_glyph::FontRef>, text: &str) -> f32 {
+    use ab_glyph::ScaleFont;
+    text.chars().map(|c| font.h_advance(font.glyph_id(c))).sum()
+}
+
+// text cut down with an ellipsis until it fits in max_width
+fn fit_caption(
+    font: &ab_glyph::PxScaleFont<&ab_glyph::FontRef>,
+    text: &str,
+    max_width: f32,
+) -> String {
+    if text_width(font, text) <= max_width {
+        return text.to_string();
+    }
+    let mut chars: Vec<char> = text.chars().collect();
+    while !chars.is_empty() {
+        chars.pop();
+        let candidate: String = chars.iter().chain(['…'].iter()).collect();
+        if text_width(font, &candidate) <= max_width {
+            return candidate;
+        }
+    }
+    String::new()
+}
+
+// draws text with its top-left corner at (x, y), alpha-blended onto the canvas
+fn draw_text(
+    canvas: &mut image::RgbImage,
+    font: &ab_glyph::PxScaleFont<&ab_glyph::FontRef>,
+    (x, y): (f32, f32),
+    text: &str,
+    color: [u8; 3],
+) {
+    use ab_glyph::{Font, ScaleFont};
+    let mut caret = x;
+    for c in text.chars() {
+        let glyph = font
+            .glyph_id(c)
+            .with_scale_and_position(font.scale(), ab_glyph::point(caret, y + font.ascent()));
+        caret += font.h_advance(glyph.id);
+        let Some(outline) = font.font().outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i64 + gx as i64;
+            let py = bounds.min.y as i64 + gy as i64;
+            if px < 0 || py < 0 || px >= canvas.width() as i64 || py >= canvas.height() as i64 {
+                return;
+            }
+            let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+            for (channel, &target) in pixel.0.iter_mut().zip(&color) {
+                let blended = *channel as f32 + (target as f32 - *channel as f32) * coverage;
+                *channel = blended.round() as u8;
+            }
+        });
+    }
+}
+
+// dest_path for the first page, then "name-2.jpg", "name-3.jpg"...
+fn sheet_page_path(dest: &Path, page: usize) -> std::path::PathBuf {
+    if page == 0 {
+        return dest.to_path_buf();
+    }
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match dest.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, page + 1, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, page + 1),
+    };
+    dest.with_file_name(name)
+}
+
+// one image showing every path as a captioned thumbnail, `cols` to a row, for sharing
+// a duplicate group. thumbnails come from the thumbnail cache. files that can't be
+// decoded get an empty cell so the grid still lines up with the list. a sheet that
+// would be taller than SHEET_MAX_HEIGHT continues on further pages next to dest_path.
+// JPEG or PNG by dest_path's extension. returns the files written
+#[tauri::command]
+fn make_contact_sheet(
+    paths: Vec<String>,
+    dest_path: String,
+    cols: u32,
+    thumb_edge: u32,
+) -> Result<Vec<String>, String> {
+    use ab_glyph::{Font, ScaleFont};
+    if paths.is_empty() {
+        return Err("No images to put on a contact sheet".to_string());
+    }
+    let dest = Path::new(&dest_path);
+    let format = match image_format(&dest_path).as_deref() {
+        Some("jpeg") => image::ImageFormat::Jpeg,
+        Some("png") => image::ImageFormat::Png,
+        _ => return Err("Contact sheets are saved as .jpg or .png".to_string()),
+    };
+    let font = ab_glyph::FontRef::try_from_slice(CAPTION_FONT).map_err(|e| e.to_string())?;
+    let font = font.as_scaled(SHEET_CAPTION_PX);
+
+    let edge = thumb_edge.clamp(32, 2048);
+    let cols = cols.clamp(1, paths.len() as u32);
+    let caption_height = font.height().ceil() as u32;
+    let cell_width = edge + SHEET_PADDING;
+    let cell_height = edge + caption_height + SHEET_PADDING;
+    let sheet_width = SHEET_PADDING + cols * cell_width;
+    let rows_per_page = ((SHEET_MAX_HEIGHT - SHEET_PADDING) / cell_height).max(1);
+
+    let thumbs: Vec<Option<image::RgbImage>> = paths
+        .par_iter()
+        .map(|path| {
+            let jpeg = cached_thumbnail(path, edge).ok()?;
+            image::load_from_memory(&jpeg).ok().map(|i| i.to_rgb8())
+        })
+        .collect();
+
+    let background = image::Rgb([30, 30, 30]);
+    let placeholder = image::Rgb([55, 55, 55]);
+    let mut written = Vec::new();
+    let per_page = (rows_per_page * cols) as usize;
+    for (page, start) in (0..paths.len()).step_by(per_page).enumerate() {
+        let end = (start + per_page).min(paths.len());
+        let rows = (end - start).div_ceil(cols as usize) as u32;
+        let mut sheet = image::RgbImage::from_pixel(
+            sheet_width,
+            SHEET_PADDING + rows * cell_height,
+            background,
+        );
+        for (slot, i) in (start..end).enumerate() {
+            let (col, row) = (slot as u32 % cols, slot as u32 / cols);
+            let (x, y) = (
+                SHEET_PADDING + col * cell_width,
+                SHEET_PADDING + row * cell_height,
+            );
+            match &thumbs[i] {
+                // centered in its square, thumbnails keep their aspect ratio
+                Some(thumb) => {
+                    let dx = (edge - thumb.width().min(edge)) / 2;
+                    let dy = (edge - thumb.height().min(edge)) / 2;
+                    image::imageops::overlay(&mut sheet, thumb, (x + dx) as i64, (y + dy) as i64);
+                }
+                None => {
+                    let cell = image::RgbImage::from_pixel(edge, edge, placeholder);
+                    image::imageops::overlay(&mut sheet, &cell, x as i64, y as i64);
+                }
+            }
+            let name = Path::new(&paths[i])
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| paths[i].clone());
+            let caption = fit_caption(&font, &name, edge as f32);
+            let caption_x = x as f32 + (edge as f32 - text_width(&font, &caption)) / 2.0;
+            draw_text(
+                &mut sheet,
+                &font,
+                (caption_x, (y + edge + 2) as f32),
+                &caption,
+                [220, 220, 220],
+            );
+        }
+
+        let page_path = sheet_page_path(dest, page);
+        sheet
+            .save_with_format(&page_path, format)
+            .map_err(|e| format!("Couldn't write {}: {}", page_path.display(), e))?;
+        written.push(page_path.to_string_lossy().to_string());
+    }
+    Ok(written)
+}
+
+// forgets cached rows (and thumbnails) for everything, or just for files under
+// folder_path. returns how many images were removed
+#[tauri::command]
+fn clear_cache(folder_path: Option<String>) -> Result<usize, String> {
+    let mut conn = db().lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let removed = match folder_path {
+        Some(ref folder) => {
+            let prefix = folder_prefix(folder);
+            tx.execute(
+                "DELETE FROM thumbnails WHERE substr(path, 1, length(?1)) = ?1",
+                params![prefix],
+            )
+            .map_err(|e| e.to_string())?;
+            tx.execute(
+                "DELETE FROM images WHERE substr(path, 1, length(?1)) = ?1",
+                params![prefix],
+            )
+            .map_err(|e| e.to_string())?
+        }
+        None => {
+            tx.execute("DELETE FROM thumbnails", [])
+                .map_err(|e| e.to_string())?;
+            tx.execute("DELETE FROM images", [])
+                .map_err(|e| e.to_string())?
+        }
+    };
+    tx.commit().map_err(|e| e.to_string())?;
+    println!("Cache cleared: {} rows removed", removed);
+    Ok(removed)
+}
+
+#[tauri::command]
+fn cache_stats() -> Result<CacheStats, String> {
+    let conn = db().lock().unwrap();
+    let (count, image_bytes) = conn
+        .query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM images",
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(CacheStats {
+        count: count as u64,
+        image_bytes: image_bytes as u64,
+        db_bytes: db_file_size(&conn),
+    })
+}
+
+// folds the WAL back into the DB and rebuilds the file to reclaim space from deleted
+// rows. VACUUM needs the DB to itself, so this refuses to run during a scan
+#[tauri::command]
+fn maintain_db() -> Result<DbMaintenance, String> {
+    if SCAN_RUNNING.load(Ordering::SeqCst) {
+        return Err("Can't compact the cache while a scan is running".to_string());
+    }
+    let conn = db().lock().unwrap();
+    let bytes_before = db_file_size(&conn);
+
+    let busy_err = |e: rusqlite::Error| match e.sqlite_error_code() {
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked) => {
+            "The cache is busy, try again in a moment".to_string()
+        }
+        _ => e.to_string(),
+    };
+    // the first column is 1 when a reader kept the checkpoint from finishing
+    let busy: i64 = conn
+        .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))
+        .map_err(busy_err)?;
+    if busy != 0 {
+        return Err("The cache is busy, try again in a moment".to_string());
+    }
+    conn.execute_batch("VACUUM;").map_err(busy_err)?;
+    rebuild_search_index(&conn).map_err(|e| e.to_string())?;
+
+    let bytes_after = db_file_size(&conn);
+    println!("DB compacted: {} -> {} bytes", bytes_before, bytes_after);
+    Ok(DbMaintenance {
+        bytes_before,
+        bytes_after,
+    })
+}
+
+// a stored setting, None if it was never set or doesn't parse as T
+fn setting<T: serde::de::DeserializeOwned>(key: &str) -> Option<T> {
+    let conn = read_db().ok()?;
+    let json: String = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+fn default_threshold() -> u32 {
+    setting(SETTING_SIMILARITY_THRESHOLD).unwrap_or(PHASH_THRESHOLD)
+}
+
+// the hamming distance images hashed with algo are grouped at: documents have their own,
+// everything else uses the threshold a command was given
+fn threshold_for(algo: HashAlgo, threshold: u32) -> u32 {
+    match algo {
+        HashAlgo::DhashDocument => setting(SETTING_DOCUMENT_THRESHOLD)
+            .unwrap_or(DOCUMENT_THRESHOLD)
+            .min(PHASH_BITS),
+        _ => threshold,
+    }
+}
+
+fn default_ahash_threshold() -> u32 {
+    setting(SETTING_AHASH_THRESHOLD).unwrap_or(AHASH_THRESHOLD)
+}
+
+// preferences that outlive the session, e.g. the last scanned folder. values are
+// whatever JSON the frontend stored, null if the key was never set
+#[tauri::command]
+fn get_setting(key: String) -> Result<serde_json::Value, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let json: Option<String> = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    match json {
+        Some(json) => serde_json::from_str(&json).map_err(|e| e.to_string()),
+        None => Ok(serde_json::Value::Null),
+    }
+}
+
+// stores `value` under `key`. null removes the setting
+#[tauri::command]
+fn set_setting(key: String, value: serde_json::Value) -> Result<(), String> {
+    let conn = db().lock().unwrap();
+    if value.is_null() {
+        conn.execute("DELETE FROM settings WHERE key = ?1", params![key])
+    } else {
+        conn.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![key, value.to_string()],
+        )
+    }
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+// nearest city further than this is as good as no answer, e.g. mid-ocean
+const MAX_PLACE_DISTANCE_KM: f64 = 50.0;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Place {
+    pub name: String,
+    pub admin1: String,       // state/region, may be empty
+    pub admin2: String,       // county/district, may be empty
+    pub country_code: String, // ISO 3166-1 alpha-2
+}
+
+impl Place {
+    // what's stored on the image and matched by ImageQuery.place
+    fn label(&self) -> String {
+        [&self.name, &self.admin1, &self.country_code]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+// coordinates rounded to 0.01° (~1 km), the granularity of the places cache
+fn place_key(lat: f64, lon: f64) -> (i64, i64) {
+    ((lat * 100.0).round() as i64, (lon * 100.0).round() as i64)
+}
+
+fn cached_place(lat: f64, lon: f64) -> Option<Place> {
+    let (lat_key, lon_key) = place_key(lat, lon);
+    let conn = read_db().ok()?;
+    let json: String = conn
+        .query_row(
+            "SELECT place_json FROM places WHERE lat_key = ?1 AND lon_key = ?2",
+            params![lat_key, lon_key],
+            |row| row.get(0),
+        )
+        .ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+// nearest populated place from the bundled GeoNames cities table, without the cache
+fn lookup_place(lat: f64, lon: f64) -> Option<Place> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return None;
+    }
+    let geocoder = GEOCODER.get_or_init(reverse_geocoder::ReverseGeocoder::new);
+    let found = geocoder.search((lat, lon));
+    // distance is the squared chord on the unit sphere
+    let km = found.distance.sqrt() * EARTH_RADIUS_KM;
+    if km > MAX_PLACE_DISTANCE_KM {
+        return None;
+    }
+    let r = found.record;
+    Some(Place {
+        name: r.name.clone(),
+        admin1: r.admin1.clone(),
+        admin2: r.admin2.clone(),
+        country_code: r.cc.clone(),
+    })
+}
+
+// place name for a GPS position, null if nothing is nearby. works offline; results
+// are cached by rounded coordinates
+#[tauri::command]
+fn reverse_geocode(lat: f64, lon: f64) -> Result<Option<Place>, String> {
+    if let Some(place) = cached_place(lat, lon) {
+        return Ok(Some(place));
+    }
+    let Some(place) = lookup_place(lat, lon) else {
+        return Ok(None);
+    };
+    let json = serde_json::to_string(&place).map_err(|e| e.to_string())?;
+    let (lat_key, lon_key) = place_key(lat, lon);
+    db().lock()
+        .unwrap()
+        .execute(
+            "INSERT OR REPLACE INTO places (lat_key, lon_key, place_json) VALUES (?1, ?2, ?3)",
+            params![lat_key, lon_key, json],
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(Some(place))
+}
+
+// resolves and stores the place of every cached image under `folder` that has GPS
+// coordinates but no place yet. returns how many were filled in
+#[tauri::command]
+fn geocode_images(folder: String) -> Result<usize, String> {
+    let prefix = folder_prefix(&folder);
+    let pending: Vec<(String, String)> = {
+        let conn = read_db().map_err(|e| e.to_string())?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT path, exif_json FROM images
+                 WHERE substr(path, 1, length(?1)) = ?1 AND place IS NULL
+                   AND exif_json IS NOT NULL",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![prefix], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<SqlResult<Vec<_>>>()
+            .map_err(|e| e.to_string())?
+    };
+
+    let mut resolved = Vec::new();
+    for (path, exif_json) in pending {
+        let Ok(exif) = serde_json::from_str::<ExifData>(&exif_json) else {
+            continue;
+        };
+        let (Some(lat), Some(lon)) = (exif.lat, exif.lon) else {
+            continue;
+        };
+        if let Some(place) = reverse_geocode(lat, lon)? {
+            resolved.push((path, place.label()));
+        }
+    }
+
+    let mut conn = db().lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    {
+        let mut stmt = tx
+            .prepare("UPDATE images SET place = ?2 WHERE path = ?1")
+            .map_err(|e| e.to_string())?;
+        for (path, label) in &resolved {
+            stmt.execute(params![path, label])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(resolved.len())
+}
+
+#[tauri::command]
+fn add_tag(path: String, tag: String) -> Result<(), String> {
+    let tag = tag.trim();
+    if tag.is_empty() {
+        return Err("Tag can't be empty".to_string());
+    }
+    let mut conn = db().lock().unwrap();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "INSERT OR IGNORE INTO tags (name) VALUES (?1)",
+        params![tag],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.execute(
+        "INSERT OR IGNORE INTO image_tags (path, tag_id)
+         SELECT ?1, id FROM tags WHERE name = ?2",
+        params![path, tag],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_tag(path: String, tag: String) -> Result<(), String> {
+    let conn = db().lock().unwrap();
+    conn.execute(
+        "DELETE FROM image_tags
+         WHERE path = ?1 AND tag_id = (SELECT id FROM tags WHERE name = ?2)",
+        params![path, tag.trim()],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM image_tags)",
+        [],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_tags(path: String) -> Result<Vec<String>, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT t.name FROM image_tags it JOIN tags t ON t.id = it.tag_id
+             WHERE it.path = ?1 ORDER BY t.name",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![path], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    rows.collect::<SqlResult<Vec<String>>>()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_images_by_tag(tag: String) -> Result<Vec<ImageInfo>, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {IMAGE_COLUMNS} FROM images WHERE path IN (
+                SELECT it.path FROM image_tags it JOIN tags t ON t.id = it.tag_id
+                WHERE t.name = ?1
+             ) ORDER BY path"
+        ))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![tag.trim()], row_to_image)
+        .map_err(|e| e.to_string())?;
+    rows.collect::<SqlResult<Vec<_>>>()
+        .map_err(|e| e.to_string())
+}
+
+// unrated images come back as the default (0, not a favorite)
+#[tauri::command]
+fn get_rating(path: String) -> Result<Rating, String> {
+    let conn = read_db().map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT rating, favorite FROM ratings WHERE path = ?1",
+        params![path],
+        |row| {
+            Ok(Rating {
+                rating: row.get(0)?,
+                favorite: row.get(1)?,
+            })
+        },
+    )
+    .optional()
+    .map(Option::unwrap_or_default)
+    .map_err(|e| e.to_string())
+}
+
+// 0 clears the rating
+#[tauri::command]
+fn set_rating(path: String, rating: i32) -> Result<(), String> {
+    if !(0..=5).contains(&rating) {
+        return Err(format!("Rating must be between 0 and 5, got {}", rating));
+    }
+    let conn = db().lock().unwrap();
+    conn.execute(
+        "INSERT INTO ratings (path, rating) VALUES (?1, ?2)
+         ON CONFLICT(path) DO UPDATE SET rating = excluded.rating",
+        params![path, rating],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// flips the favorite flag, returning the new state
+#[tauri::command]
+fn toggle_favorite(path: String) -> Result<bool, String> {
+    let conn = db().lock().unwrap();
+    conn.query_row(
+        "INSERT INTO ratings (path, favorite) VALUES (?1, 1)
+         ON CONFLICT(path) DO UPDATE SET favorite = NOT favorite
+         RETURNING favorite",
+        params![path],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_thumbnail_cache_stats() -> Result<ThumbnailCacheStats, String> {
+    thumb_cache_stats().map_err(|e| e.to_string())
+}
+
+// drops every cached thumbnail, returning what was freed
+#[tauri::command]
+fn clear_thumbnail_cache() -> Result<ThumbnailCacheStats, String> {
+    let freed = thumb_cache_stats().map_err(|e| e.to_string())?;
+    let conn = db().lock().unwrap();
+    conn.execute("DELETE FROM thumbnails", [])
+        .map_err(|e| e.to_string())?;
+    Ok(freed)
+}
+
+// opens the file in the system's default image viewer
 #[tauri::command]
 fn open_image(path: String) -> Result<(), String> {
+    reject_archive_entry(&path)?;
     #[cfg(target_os = "windows")]
     {
-        std::process::Command::new("explorer")
-            .arg(&path)
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        // `start` takes the first quoted argument as a window title, hence the empty one.
+        // raw_arg because cmd's quoting rules aren't the ones Command escapes for
+        std::process::Command::new("cmd")
+            .raw_arg(format!("/C start \"\" \"{}\"", path))
+            .creation_flags(CREATE_NO_WINDOW)
             .spawn()
             .map_err(|e| e.to_string())?;
     }
@@ -456,8 +7386,78 @@ fn open_image(path: String) -> Result<(), String> {
     Ok(())
 }
 
+// file:// URI for the FileManager1 D-Bus interface, percent-encoding everything but
+// unreserved characters and path separators
+#[cfg(target_os = "linux")]
+fn file_uri(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    let mut uri = String::from("file://");
+    for &b in path.as_os_str().as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                uri.push(b as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+// shows the file selected in the platform's file manager
+#[tauri::command]
+fn reveal_in_folder(path: String) -> Result<(), String> {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        // explorer parses its own command line and wants the path quoted after the comma
+        std::process::Command::new("explorer")
+            .raw_arg(format!("/select,\"{}\"", path))
+            .spawn()
+            .map_err(|e| e.to_string())?;
+    }
+    #[cfg(target_os = "macos")]
+    {
+        std::process::Command::new("open")
+            .arg("-R")
+            .arg(&path)
+            .spawn()
+            .map_err(|e| e.to_string())?;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // most file managers (Nautilus, Dolphin, Nemo, ...) implement FileManager1.ShowItems.
+        // if none does, fall back to just opening the containing folder
+        let shown = std::process::Command::new("dbus-send")
+            .args([
+                "--session",
+                "--dest=org.freedesktop.FileManager1",
+                "--type=method_call",
+                "/org/freedesktop/FileManager1",
+                "org.freedesktop.FileManager1.ShowItems",
+            ])
+            .arg(format!("array:string:{}", file_uri(Path::new(&path))))
+            .arg("string:")
+            .status()
+            .is_ok_and(|s| s.success());
+        if !shown {
+            let parent = Path::new(&path)
+                .parent()
+                .ok_or("path has no parent folder")?;
+            std::process::Command::new("xdg-open")
+                .arg(parent)
+                .spawn()
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // JPEG XL is decoded by jxl-oxide, which plugs into the image crate's format detection
+    // so every load and image_dimensions call picks it up
+    jxl_oxide::integration::register_image_decoding_hook();
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .setup(|app| {
@@ -471,16 +7471,1073 @@ pub fn run() {
             let dir_str = app_data_dir.to_string_lossy().to_string();
             let conn = init_db(&dir_str).expect("Failed to initialize DB");
             DB.set(Mutex::new(conn)).expect("DB already initialized");
+            let pool = init_read_pool(&dir_str).expect("Failed to open DB read pool");
+            READ_POOL
+                .set(pool)
+                .expect("DB read pool already initialized");
+            let writer = spawn_writer(&dir_str).expect("Failed to open DB writer");
+            WRITER.set(writer).expect("DB writer already initialized");
+            SIMILARITY_INDEX_PATH
+                .set(app_data_dir.join("similarity_index.json"))
+                .expect("Similarity index path already set");
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             scan_folder,
+            scan_folders,
+            cancel_scan,
+            get_cached_images,
+            query_images,
+            get_images_page,
             find_similar_duplicates,
+            find_similar_duplicates_scored,
             find_exact_duplicates,
+            find_exact_duplicates_cached,
+            find_similar_duplicates_cached,
+            rebuild_similarity_index,
+            search,
+            export_report,
+            find_raw_jpeg_pairs,
+            detect_blurry,
+            analyze_exposure,
+            classify_images,
+            find_bursts,
+            group_by_location,
+            compare_two_folders,
+            missing_in_target,
+            watch_folder,
+            stop_watching,
+            summarize_duplicate_groups,
+            auto_select_keepers,
             delete_images,
+            hardlink_duplicates,
+            get_exif_raw,
+            set_exif_date,
+            restore_mtime_from_exif,
+            strip_metadata,
+            move_files,
+            copy_files,
+            organize_by_date,
+            undo_last_operation,
+            compute_color_signatures,
+            group_by_color,
+            find_cropped_duplicates,
+            get_quarantined,
+            get_folder_stats,
+            verify_library,
+            compare_images,
+            diff_images,
+            find_broken_images,
             open_image,
+            reveal_in_folder,
+            get_image_data,
+            get_thumbnail,
+            get_thumbnail_cache_stats,
+            pregenerate_thumbnails,
+            cancel_thumbnails,
+            make_contact_sheet,
+            clear_thumbnail_cache,
+            clear_cache,
+            get_setting,
+            get_extensions,
+            set_extensions,
+            set_setting,
+            reverse_geocode,
+            geocode_images,
+            add_tag,
+            remove_tag,
+            get_tags,
+            get_images_by_tag,
+            get_rating,
+            set_rating,
+            toggle_favorite,
+            cache_stats,
+            maintain_db,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the global DB, read pool and writer, set up once per test binary in a temp dir that
+    // outlives it
+    fn test_db() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            let dir = tempfile::tempdir().unwrap().keep();
+            let dir = dir.to_string_lossy();
+            DB.set(Mutex::new(init_db(&dir).unwrap())).unwrap();
+            READ_POOL.set(init_read_pool(&dir).unwrap()).unwrap();
+            WRITER.set(spawn_writer(&dir).unwrap()).unwrap();
+        });
+    }
+
+    // a diagonal gradient with a block in one corner, different for each seed
+    fn test_image(w: u32, h: u32, seed: u32) -> image::DynamicImage {
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(w, h, |x, y| {
+            let v = ((x * 255 / w + y * 128 / h + seed * 37) % 256) as u8;
+            let block = x < w / 3 && y < h / 3;
+            image::Rgb(if block {
+                [255 - v, v, 128]
+            } else {
+                [v, v / 2, 255 - v]
+            })
+        }))
+    }
+
+    fn encode(img: &image::DynamicImage, format: image::ImageFormat) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut out, format).unwrap();
+        out.into_inner()
+    }
+
+    fn encode_jpeg(img: &image::DynamicImage) -> Vec<u8> {
+        let mut out = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 100)
+            .encode_image(&img.to_rgb8())
+            .unwrap();
+        out
+    }
+
+    // the JPEG with these EXIF tags written into it
+    fn with_exif(mut jpeg: Vec<u8>, tags: Vec<little_exif::exif_tag::ExifTag>) -> Vec<u8> {
+        let mut metadata = little_exif::metadata::Metadata::new();
+        for tag in tags {
+            metadata.set_tag(tag);
+        }
+        metadata
+            .write_to_vec(&mut jpeg, little_exif::filetype::FileExtension::JPEG)
+            .unwrap();
+        jpeg
+    }
+
+    fn test_row(path: String) -> ImageInfo {
+        ImageInfo {
+            name: path.rsplit('/').next().unwrap().to_string(),
+            path,
+            size: 1000,
+            created_at: 1,
+            modified_at: 2,
+            phash: Some(42),
+            ahash: Some(7),
+            sha256: Some("abc".into()),
+            exif: None,
+            phash_rotations: None,
+            phash_mirror: None,
+            tile_hashes: None,
+            metadata: None,
+            place: None,
+            hash_algo: HashAlgo::Dhash,
+            hashes_computed: true,
+            metadata_level: MetadataLevel::Full,
+        }
+    }
+
+    // scans share SCAN_CANCELLED and SCAN_RUNNING, so tests that run one take turns
+    static SCAN_LOCK: Mutex<()> = Mutex::new(());
+
+    // n distinct PNGs in dir, named 000.png, 001.png, ...
+    fn write_images(dir: &Path, n: u32) {
+        for i in 0..n {
+            let png = encode(&test_image(32, 24, i), image::ImageFormat::Png);
+            fs::write(dir.join(format!("{i:03}.png")), png).unwrap();
+        }
+    }
+
+    // xorshift64, so randomized tests are reproducible without a rand dependency
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, bytes) in entries {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(bytes).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    // a PNG whose IHDR claims w x h RGB pixels, followed by an empty IDAT
+    fn png_header(w: u32, h: u32) -> Vec<u8> {
+        let mut ihdr = Vec::new();
+        ihdr.extend(w.to_be_bytes());
+        ihdr.extend(h.to_be_bytes());
+        ihdr.extend([8, 2, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [
+            (b"IHDR", ihdr),
+            (b"IDAT", Vec::new()),
+            (b"IEND", Vec::new()),
+        ] {
+            png.extend((data.len() as u32).to_be_bytes());
+            let start = png.len();
+            png.extend(kind);
+            png.extend(&data);
+            png.extend(crc32fast::hash(&png[start..]).to_be_bytes());
+        }
+        png
+    }
+
+    #[test]
+    fn decode_checked_rejects_oversized_header_before_decoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("huge.png");
+        fs::write(&path, png_header(4000, 4000)).unwrap();
+
+        let err = decode_checked(&path, None, 10_000_000).unwrap_err();
+        assert!(err.contains("megapixel limit"), "{err}");
+        // under a limit it fits, the same file gets as far as the decoder
+        let err = decode_checked(&path, None, MAX_IMAGE_PIXELS).unwrap_err();
+        assert!(err.starts_with("corrupt or truncated"), "{err}");
+        // and the decoder enforces the same limit on its own
+        let err = load_oriented_file(&path, None, decode_limits(1_000_000)).unwrap_err();
+        assert!(err.starts_with("image too large"), "{err}");
+    }
+
+    #[test]
+    fn decode_checked_rejects_unmeasurable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("garbage.png");
+        fs::write(&path, b"not an image at all").unwrap();
+
+        let err = decode_checked(&path, None, MAX_IMAGE_PIXELS).unwrap_err();
+        assert!(err.contains("dimensions unreadable"), "{err}");
+    }
+    #[test]
+    fn scan_units_group_an_archive_into_one_unit() {
+        let dir = tempfile::tempdir().unwrap();
+        let png = encode(&test_image(32, 32, 1), image::ImageFormat::Png);
+        let archive = dir.path().join("photos.zip");
+        write_zip(&archive, &[("a.png", &png), ("sub/b.png", &png)]);
+        let loose = dir.path().join("photos.zip.png");
+        fs::write(&loose, &png).unwrap();
+
+        let mut paths = vec![
+            (archive_entry_path(&archive, "sub/b.png"), 1),
+            (loose.clone(), 1),
+            (archive_entry_path(&archive, "a.png"), 1),
+        ];
+        paths.sort();
+        let units = scan_units(paths);
+        assert_eq!(units.len(), 2);
+        let entries = units
+            .iter()
+            .find_map(|u| match u {
+                ScanUnit::Archive {
+                    archive: a,
+                    entries,
+                } if *a == archive => Some(entries),
+                _ => None,
+            })
+            .unwrap();
+        let names: Vec<&str> = entries.iter().map(|(_, name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a.png", "sub/b.png"]);
+        assert!(units
+            .iter()
+            .any(|u| matches!(u, ScanUnit::File(p, _) if *p == loose)));
+    }
+
+    #[test]
+    fn archive_entries_are_read_from_one_open_archive_and_cached() {
+        test_db();
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("photos.zip");
+        let a = encode(&test_image(64, 48, 1), image::ImageFormat::Png);
+        let b = encode(&test_image(64, 48, 2), image::ImageFormat::Png);
+        write_zip(&archive, &[("a.png", &a), ("b.png", &b)]);
+        let options = ScanOptions::default();
+
+        let mut opened = OpenArchive::open(&archive).unwrap();
+        let mut fresh = Vec::new();
+        for entry in ["a.png", "b.png"] {
+            let path = archive_entry_path(&archive, entry);
+            let processed = process_archive_entry(&path, &mut opened, entry, &options).unwrap();
+            assert!(!processed.from_cache);
+            assert!(processed.info.phash.is_some());
+            fresh.push(processed.info);
+        }
+        cache_set_batch(&fresh).unwrap();
+
+        for info in &fresh {
+            let entry = info.path.rsplit('/').next().unwrap();
+            let path = Path::new(&info.path);
+            let processed = process_archive_entry(path, &mut opened, entry, &options).unwrap();
+            assert!(processed.from_cache);
+            assert_eq!(processed.info.phash, info.phash);
+        }
+    }
+    #[test]
+    fn baseline_cache_migrates_to_the_latest_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_str = dir.path().to_string_lossy();
+        {
+            // the schema and rows as the app wrote them before versioning existed
+            let conn = Connection::open(db_path(&dir_str)).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE images (
+                    path        TEXT PRIMARY KEY,
+                    name        TEXT NOT NULL,
+                    size        INTEGER NOT NULL,
+                    created_at  INTEGER NOT NULL,
+                    modified_at INTEGER NOT NULL,
+                    phash       TEXT,
+                    sha1        TEXT,
+                    exif_json   TEXT
+                );
+                CREATE INDEX idx_phash ON images(phash);
+                CREATE INDEX idx_sha1  ON images(sha1);",
+            )
+            .unwrap();
+            let rows = [
+                (
+                    "/photos/a.jpg",
+                    Some("f0f0f0f0f0f0f0f0"),
+                    r#"{"width":4000,"height":3000}"#,
+                ),
+                ("/photos/b.png", None, "null"),
+            ];
+            for (path, phash, exif) in rows {
+                conn.execute(
+                    "INSERT INTO images
+                        (path, name, size, created_at, modified_at, phash, sha1, exif_json)
+                     VALUES (?1, ?2, 1234, 100, 200, ?3, ?4, ?5)",
+                    params![path, &path[8..], phash, format!("sha-{}", &path[8..]), exif],
+                )
+                .unwrap();
+            }
+        }
+
+        let conn = init_db(&dir_str).unwrap();
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as i64);
+        let indexes: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'images'")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<SqlResult<_>>()
+            .unwrap();
+        assert!(indexes.iter().any(|i| i == "idx_sha256"));
+        assert!(!indexes.iter().any(|i| i == "idx_sha1"));
+
+        let images = cache_list(&conn, None).unwrap();
+        assert_eq!(images.len(), 2);
+        let a = &images[0];
+        assert_eq!(a.path, "/photos/a.jpg");
+        assert_eq!((a.size, a.created_at, a.modified_at), (1234, 100, 200));
+        assert_eq!(a.phash, Some(0xf0f0_f0f0_f0f0_f0f0));
+        assert_eq!(a.sha256.as_deref(), Some("sha-a.jpg"));
+        assert_eq!(a.exif.as_ref().and_then(|e| e.width), Some(4000));
+        assert!(a.hashes_computed);
+        assert_eq!(images[1].phash, None);
+        drop(conn);
+
+        // already current, so opening it again changes nothing
+        let conn = init_db(&dir_str).unwrap();
+        assert_eq!(cache_list(&conn, None).unwrap().len(), 2);
+    }
+    #[test]
+    fn bk_tree_grouping_matches_brute_force() {
+        let mut state = 0x9e37_79b9_7f4a_7c15;
+        // clusters of near-identical hashes among unrelated ones, so every threshold
+        // has something to group
+        let mut hashes: Vec<Vec<u64>> = Vec::new();
+        for _ in 0..60 {
+            let center = next_random(&mut state);
+            for _ in 0..next_random(&mut state) % 4 + 1 {
+                let mut hash = center;
+                for _ in 0..next_random(&mut state) % 8 {
+                    hash ^= 1 << (next_random(&mut state) % 64);
+                }
+                hashes.push(vec![hash]);
+            }
+        }
+
+        for threshold in [0, 2, 5, 10, 24] {
+            let mut expected = DisjointSet::new(hashes.len());
+            for i in 0..hashes.len() {
+                for j in i + 1..hashes.len() {
+                    if phash_distance(hashes[i][0], hashes[j][0]) <= threshold {
+                        expected.union(i, j);
+                    }
+                }
+            }
+            let grouped = group_similar(&hashes, threshold, &|_, _| true);
+            assert!(threshold < 5 || !grouped.is_empty());
+            assert_eq!(grouped, expected.groups(), "threshold {threshold}");
+        }
+    }
+    #[test]
+    fn chained_matches_end_up_in_one_group() {
+        // A~B and B~C at threshold 3, while A and C are 6 bits apart
+        let (a, b, c) = (0u64, 0b111, 0b111_111);
+        assert_eq!(phash_distance(a, c), 6);
+        let hashes = vec![vec![a], vec![0xffff_0000_ffff_0000], vec![b], vec![c]];
+        assert_eq!(group_similar(&hashes, 3, &|_, _| true), vec![vec![0, 2, 3]]);
+        // whichever end the chain is joined from
+        let mut sets = DisjointSet::new(3);
+        sets.union(2, 1);
+        sets.union(0, 1);
+        assert_eq!(sets.groups(), vec![vec![0, 1, 2]]);
+    }
+    #[test]
+    fn cancelling_mid_scan_returns_the_partial_result() {
+        use tauri::Listener;
+        test_db();
+        let _scan = SCAN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        write_images(dir.path(), 100);
+        let app = tauri::test::mock_app();
+        app.listen_any("scan-progress", |event| {
+            let progress: serde_json::Value = serde_json::from_str(event.payload()).unwrap();
+            if progress["phase"] == "hashing" && progress["current"].as_u64() >= Some(20) {
+                SCAN_CANCELLED.store(true, Ordering::SeqCst);
+            }
+        });
+
+        let root = dir.path().to_string_lossy().to_string();
+        let options = ScanOptions {
+            threads: Some(1),
+            ..Default::default()
+        };
+        let images = scan_roots(&[root], true, options, app.handle()).unwrap();
+        assert!(!images.is_empty() && images.len() < 100, "{}", images.len());
+        assert!(!SCAN_RUNNING.load(Ordering::SeqCst));
+        // what was hashed before the cancel is kept
+        for img in &images {
+            let size = fs::metadata(&img.path).unwrap().len();
+            assert!(cache_get(&img.path, img.modified_at, size).is_some());
+        }
+    }
+    #[test]
+    fn gps_coordinates_are_signed_by_their_refs() {
+        use little_exif::exif_tag::ExifTag;
+        use little_exif::rational::uR64;
+        let dms = |d, m, s_tenths| {
+            vec![
+                uR64 {
+                    nominator: d,
+                    denominator: 1,
+                },
+                uR64 {
+                    nominator: m,
+                    denominator: 1,
+                },
+                uR64 {
+                    nominator: s_tenths,
+                    denominator: 10,
+                },
+            ]
+        };
+        let jpeg = encode_jpeg(&test_image(16, 16, 0));
+        let tagged = |lat_ref: &str, lon_ref: &str| {
+            let bytes = with_exif(
+                jpeg.clone(),
+                vec![
+                    ExifTag::GPSLatitudeRef(lat_ref.to_string()),
+                    ExifTag::GPSLatitude(dms(33, 51, 354)),
+                    ExifTag::GPSLongitudeRef(lon_ref.to_string()),
+                    ExifTag::GPSLongitude(dms(151, 12, 3)),
+                ],
+            );
+            let exif = compute_exif(&bytes).unwrap();
+            (exif.lat.unwrap(), exif.lon.unwrap())
+        };
+        let lat = 33.0 + 51.0 / 60.0 + 35.4 / 3600.0;
+        let lon = 151.0 + 12.0 / 60.0 + 0.3 / 3600.0;
+
+        let (south, west) = tagged("S", "W");
+        assert!((south + lat).abs() < 1e-9, "{south}");
+        assert!((west + lon).abs() < 1e-9, "{west}");
+        let (north, east) = tagged("N", "E");
+        assert!((north - lat).abs() < 1e-9, "{north}");
+        assert!((east - lon).abs() < 1e-9, "{east}");
+    }
+    #[test]
+    fn utc_offsets_parse_with_either_sign() {
+        assert_eq!(parse_utc_offset("+09:00"), Some(9 * 3600));
+        assert_eq!(parse_utc_offset("-05:30"), Some(-(5 * 3600 + 30 * 60)));
+        assert_eq!(parse_utc_offset("\"+00:00\""), Some(0));
+        assert_eq!(parse_utc_offset("09:00"), None);
+        assert_eq!(parse_utc_offset("+15:00"), None);
+        assert_eq!(parse_utc_offset(""), None);
+    }
+
+    #[test]
+    fn exif_timestamps_are_shifted_to_utc_by_their_offset() {
+        let local = "2024:03:10 12:00:00";
+        let as_utc = chrono::NaiveDate::from_ymd_opt(2024, 3, 10)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp();
+        assert_eq!(
+            exif_timestamp(local, Some("+09:00")),
+            Some(as_utc - 9 * 3600)
+        );
+        assert_eq!(
+            exif_timestamp(local, Some("-05:30")),
+            Some(as_utc + 5 * 3600 + 30 * 60)
+        );
+        // no offset, or one that doesn't parse, leaves the local time as UTC
+        assert_eq!(exif_timestamp(local, None), Some(as_utc));
+        assert_eq!(exif_timestamp(local, Some("garbage")), Some(as_utc));
+        assert_eq!(exif_timestamp("2024-03-10 12:00:00", None), None);
+    }
+    #[test]
+    fn exif_rotated_copy_hashes_like_the_upright_original() {
+        use little_exif::exif_tag::ExifTag;
+        test_db();
+        let dir = tempfile::tempdir().unwrap();
+        let upright = test_image(96, 64, 3);
+        let original = dir.path().join("upright.jpg");
+        fs::write(&original, encode_jpeg(&upright)).unwrap();
+        let options = ScanOptions::default();
+        let expected = process_image_file(&original, &options).unwrap().info.phash;
+        assert!(expected.is_some());
+
+        for orientation in 2..=8u16 {
+            // stored the way a camera would, so that applying the tag turns it upright
+            let inverse = match orientation {
+                6 => 8,
+                8 => 6,
+                o => o,
+            };
+            let stored = apply_orientation(upright.clone(), u32::from(inverse));
+            let bytes = with_exif(
+                encode_jpeg(&stored),
+                vec![ExifTag::Orientation(vec![orientation])],
+            );
+            let path = dir.path().join(format!("rotated-{orientation}.jpg"));
+            fs::write(&path, bytes).unwrap();
+            let info = process_image_file(&path, &options).unwrap().info;
+            assert_eq!(
+                info.exif.and_then(|e| e.orientation),
+                Some(orientation as u32)
+            );
+            assert_eq!(info.phash, expected, "orientation {orientation}");
+        }
+    }
+    #[test]
+    fn writer_commits_every_queued_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_str = dir.path().to_string_lossy();
+        let conn = init_db(&dir_str).unwrap();
+        let count = |prefix: &str| -> i64 {
+            conn.query_row(
+                "SELECT COUNT(*) FROM images WHERE path LIKE ?1 || '%'",
+                params![prefix],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        // more than one batch, and a partial one at the end
+        let rows = CACHE_BATCH_SIZE * 2 + 17;
+
+        let writer = spawn_writer(&dir_str).unwrap();
+        for i in 0..rows {
+            let row = test_row(format!("/flushed/{i}.jpg"));
+            writer.send(WriterMsg::Image(Box::new(row))).unwrap();
+        }
+        let (reply, done) = std::sync::mpsc::channel();
+        writer.send(WriterMsg::Flush(reply)).unwrap();
+        done.recv().unwrap().unwrap();
+        assert_eq!(count("/flushed/"), rows as i64);
+
+        // and without a flush, once the channel closes
+        let (tx, rx) = std::sync::mpsc::channel();
+        let writer_conn = Connection::open(db_path(&dir_str)).unwrap();
+        let thread = std::thread::spawn(move || run_writer(writer_conn, rx));
+        for i in 0..rows {
+            let row = test_row(format!("/shutdown/{i}.jpg"));
+            tx.send(WriterMsg::Image(Box::new(row))).unwrap();
+        }
+        drop(tx);
+        thread.join().unwrap();
+        assert_eq!(count("/shutdown/"), rows as i64);
+    }
+    #[test]
+    fn same_second_shots_are_ordered_by_subsec_time() {
+        use little_exif::exif_tag::ExifTag;
+        let jpeg = encode_jpeg(&test_image(16, 16, 0));
+        // path order is the opposite of capture order
+        let shots = [("a.jpg", "870"), ("b.jpg", "5"), ("c.jpg", "120")];
+        let images: Vec<ImageInfo> = shots
+            .iter()
+            .map(|(name, subsec)| {
+                let bytes = with_exif(
+                    jpeg.clone(),
+                    vec![
+                        ExifTag::Model("Burst Cam".to_string()),
+                        ExifTag::DateTimeOriginal("2024:06:01 10:15:30".to_string()),
+                        ExifTag::SubSecTimeOriginal(subsec.to_string()),
+                    ],
+                );
+                let mut info = test_row(format!("/burst/{name}"));
+                info.exif = compute_exif(&bytes);
+                info
+            })
+            .collect();
+        let dates: Vec<_> = images
+            .iter()
+            .map(|i| i.exif.as_ref().unwrap().date)
+            .collect();
+        assert!(dates[0].is_some() && dates.iter().all(|d| *d == dates[0]));
+        let millis: Vec<i64> = images.iter().map(|i| image_date_ms(i) % 1000).collect();
+        assert_eq!(millis, [870, 500, 120]);
+
+        let bursts = find_bursts(images, Some(1));
+        assert_eq!(bursts.len(), 1);
+        let order: Vec<&str> = bursts[0].iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(order, ["c.jpg", "b.jpg", "a.jpg"]);
+    }
+    #[test]
+    fn horizontally_flipped_copy_groups_with_its_original() {
+        test_db();
+        let dir = tempfile::tempdir().unwrap();
+        let original = test_image(120, 80, 5);
+        let paths = [
+            dir.path().join("selfie.png"),
+            dir.path().join("mirrored.png"),
+        ];
+        fs::write(&paths[0], encode(&original, image::ImageFormat::Png)).unwrap();
+        fs::write(
+            &paths[1],
+            encode(&original.fliph(), image::ImageFormat::Png),
+        )
+        .unwrap();
+        let options = ScanOptions {
+            mirror_invariant: true,
+            ..Default::default()
+        };
+        let images: Vec<ImageInfo> = paths
+            .iter()
+            .map(|p| process_image_file(p, &options).unwrap().info)
+            .collect();
+        assert!(images.iter().all(|i| i.phash_mirror.is_some()));
+
+        let upright: Vec<Vec<u64>> = images.iter().map(|i| vec![i.phash.unwrap()]).collect();
+        assert!(group_similar(&upright, PHASH_THRESHOLD, &|_, _| true).is_empty());
+        let variants: Vec<Vec<u64>> = images.iter().filter_map(hash_variants).collect();
+        assert_eq!(
+            group_similar(&variants, PHASH_THRESHOLD, &|_, _| true),
+            vec![vec![0, 1]]
+        );
+        assert_eq!(image_distance(&images[0], &images[1]), Ok(0));
+    }
+    // a dark 16-bit scan: all of it within the bottom 1/128th of the range, where narrowing
+    // to 8 bits leaves only a couple of levels
+    fn dark_scan(seed: u32) -> image::DynamicImage {
+        image::DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(160, 120, |x, y| {
+            let wave = ((x as f64 / (7.0 + seed as f64)).sin() + (y as f64 / 11.0).cos()) * 120.0;
+            image::Luma([(256.0 + wave + (x * seed) as f64 % 17.0) as u16])
+        }))
+    }
+
+    #[test]
+    fn sixteen_bit_sources_hash_stably_and_distinctly() {
+        let (a, b) = (dark_scan(1), dark_scan(4));
+        assert!(a.to_luma8().pixels().all(|p| p.0[0] <= 2));
+        let hash_a = compute_phash(&a);
+        assert_ne!(hash_a, 0);
+
+        // a 16-bit PNG and TIFF of the scan hash the same as the decoded image
+        for format in [image::ImageFormat::Png, image::ImageFormat::Tiff] {
+            let bytes = encode(&a, format);
+            let decoded = load_oriented(&bytes, None, image::Limits::default()).unwrap();
+            assert!(is_high_bit_depth(&decoded), "{format:?}");
+            assert_eq!(compute_phash(&decoded), hash_a, "{format:?}");
+        }
+        // and a different scan in the same narrow range still hashes apart
+        assert!(phash_distance(hash_a, compute_phash(&b)) > PHASH_THRESHOLD);
+    }
+    #[test]
+    fn resized_export_groups_with_its_full_size_original() {
+        // soft shapes over a gradient, roughly what a photo looks like at hash scale
+        let original =
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(4000, 3000, |x, y| {
+                let (fx, fy) = (x as f64 / 4000.0, y as f64 / 3000.0);
+                let sun = (-((fx - 0.7).powi(2) + (fy - 0.3).powi(2)) * 30.0).exp();
+                let hill = if fy > 0.6 + 0.1 * (fx * 9.0).sin() {
+                    0.5
+                } else {
+                    0.0
+                };
+                let v = |base: f64| ((base + sun - hill).clamp(0.0, 1.0) * 255.0) as u8;
+                image::Rgb([v(0.2 + fy * 0.4), v(0.3 + fx * 0.3), v(0.8 - fy * 0.5)])
+            }));
+        let export = original.resize(640, 480, image::imageops::FilterType::Triangle);
+        let mut small = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut small, 60)
+            .encode_image(&export.to_rgb8())
+            .unwrap();
+
+        let limits = image::Limits::default();
+        let decoded = [
+            load_oriented(&encode_jpeg(&original), None, limits.clone()).unwrap(),
+            load_oriented(&small, None, limits).unwrap(),
+        ];
+        assert_eq!(decoded[1].width(), 640);
+        let hashes: Vec<Vec<u64>> = decoded
+            .iter()
+            .map(|img| vec![compute_hash(img, HashAlgo::DhashNormalized)])
+            .collect();
+        assert_eq!(
+            group_similar(&hashes, PHASH_THRESHOLD, &|_, _| true),
+            vec![vec![0, 1]]
+        );
+    }
+    #[test]
+    fn cmyk_and_grayscale_jpegs_hash_like_the_rgb_image() {
+        let rgb = test_image(128, 96, 7);
+        let expected = compute_phash(&rgb);
+        let cmyk: Vec<u8> = rgb
+            .to_rgb8()
+            .pixels()
+            .flat_map(|p| [255 - p.0[0], 255 - p.0[1], 255 - p.0[2], 0])
+            .collect();
+
+        // plain Adobe CMYK as scanners write it, and the YCCK variant Photoshop prefers
+        for color in [
+            jpeg_encoder::ColorType::Cmyk,
+            jpeg_encoder::ColorType::CmykAsYcck,
+        ] {
+            let mut jpeg = Vec::new();
+            jpeg_encoder::Encoder::new(&mut jpeg, 95)
+                .encode(&cmyk, 128, 96, color)
+                .unwrap();
+            let decoded = load_oriented(&jpeg, None, image::Limits::default()).unwrap();
+            // converted to RGB, and not left with inverted colors
+            let decoded = decoded.as_rgb8().expect("CMYK decodes to RGB");
+            let diff: u64 = decoded
+                .as_raw()
+                .iter()
+                .zip(rgb.to_rgb8().as_raw())
+                .map(|(&a, &b)| a.abs_diff(b) as u64)
+                .sum();
+            assert!(diff / (decoded.len() as u64) < 8, "{color:?}");
+            let hash = compute_phash(&image::DynamicImage::ImageRgb8(decoded.clone()));
+            assert!(phash_distance(hash, expected) <= 2, "{color:?}");
+        }
+
+        let gray = encode(
+            &image::DynamicImage::ImageLuma8(rgb.to_luma8()),
+            image::ImageFormat::Jpeg,
+        );
+        let decoded = load_oriented(&gray, None, image::Limits::default()).unwrap();
+        assert!(decoded.as_luma8().is_some());
+        assert!(phash_distance(compute_phash(&decoded), expected) <= 2);
+    }
+    #[test]
+    fn jxl_fixture_decodes_and_hashes_like_its_source() {
+        jxl_oxide::integration::register_image_decoding_hook();
+        let img = test_image(96, 64, 7);
+        let options = zune_core::options::EncoderOptions::new(
+            96,
+            64,
+            zune_core::colorspace::ColorSpace::RGB,
+            zune_core::bit_depth::BitDepth::Eight,
+        );
+        let rgb = img.to_rgb8();
+        let jxl = zune_jpegxl::JxlSimpleEncoder::new(rgb.as_raw(), options)
+            .encode()
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.jxl");
+        fs::write(&path, jxl).unwrap();
+        assert_eq!(file_dimensions(&path), Some((96, 64)));
+        // the encoder is lossless, so the hash is exactly the source's
+        let decoded = decode_checked(&path, None, 1_000_000).unwrap();
+        assert_eq!(compute_phash(&decoded), compute_phash(&img));
+    }
+    #[cfg(not(feature = "avif"))]
+    #[test]
+    fn avif_without_its_decoder_is_refused_not_quarantined() {
+        let mut avif = vec![0, 0, 0, 20];
+        avif.extend_from_slice(b"ftypavif\0\0\0\0mif1");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.avif");
+        fs::write(&path, &avif).unwrap();
+
+        assert!(lacks_decoder(&path));
+        assert_eq!(
+            decode_checked(&path, None, 1_000_000).unwrap_err(),
+            "no decoder for this format in this build"
+        );
+        assert!(load_image(&avif, image::Limits::default())
+            .unwrap_err()
+            .contains("AVIF decoding not available"));
+    }
+    // an A4 page at 150dpi: paragraphs of word-sized dark blocks on off-white paper
+    fn document_page(seed: u64) -> image::GrayImage {
+        let mut page = image::GrayImage::from_pixel(1240, 1754, image::Luma([245]));
+        let mut state = seed;
+        let mut y = 120;
+        while y < 1620 {
+            for _ in 0..next_random(&mut state) % 6 + 3 {
+                let mut x = 110;
+                let end = 1130 - (next_random(&mut state) % 300) as u32;
+                while x < end {
+                    let word = 20 + (next_random(&mut state) % 90) as u32;
+                    for py in y..y + 14 {
+                        for px in x..(x + word).min(end) {
+                            page.put_pixel(px, py, image::Luma([30]));
+                        }
+                    }
+                    x += word + 12;
+                }
+                y += 26;
+            }
+            y += 40 + (next_random(&mut state) % 60) as u32;
+        }
+        page
+    }
+
+    // the same page through a scanner: shifted, unevenly lit, grainy and dusty
+    fn rescan(page: &image::GrayImage, seed: u64) -> image::DynamicImage {
+        let mut state = seed;
+        let mut uniform = || (next_random(&mut state) % 1_000_000) as f64 / 1_000_000.0;
+        let (dx, dy) = (10, -8);
+        let (w, h) = page.dimensions();
+        let scan = image::GrayImage::from_fn(w, h, |x, y| {
+            let (sx, sy) = (x as i64 - dx, y as i64 - dy);
+            let paper = if sx < 0 || sy < 0 || sx >= w as i64 || sy >= h as i64 {
+                245.0
+            } else {
+                page.get_pixel(sx as u32, sy as u32)[0] as f64
+            };
+            let shade = 1.0 - 0.15 * (x + y) as f64 / (w + h) as f64;
+            let (u1, u2) = (uniform().max(1e-9), uniform());
+            let grain = 18.0 * (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+            let dust = if uniform() < 0.003 { -200.0 } else { 0.0 };
+            image::Luma([(paper * shade + grain + dust).clamp(0.0, 255.0) as u8])
+        });
+        image::DynamicImage::ImageLuma8(scan)
+    }
+
+    #[test]
+    fn noisy_rescan_of_a_page_is_within_the_document_threshold() {
+        let (page, other) = (document_page(11), document_page(12));
+        let clean = compute_hash(
+            &image::DynamicImage::ImageLuma8(page.clone()),
+            HashAlgo::DhashDocument,
+        );
+        let scanned = compute_hash(&rescan(&page, 5), HashAlgo::DhashDocument);
+        let different = compute_hash(&rescan(&other, 5), HashAlgo::DhashDocument);
+        assert!(phash_distance(clean, scanned) <= DOCUMENT_THRESHOLD);
+        assert!(phash_distance(clean, different) > DOCUMENT_THRESHOLD);
+    }
+    #[test]
+    fn a_file_of_each_decodable_extension_is_picked_up() {
+        use image::ImageFormat::*;
+        test_db();
+        let img = test_image(48, 32, 3);
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("a.jpg", encode_jpeg(&img)),
+            ("b.JPEG", encode_jpeg(&img)),
+            ("c.png", encode(&img, Png)),
+            ("d.webp", encode(&img, WebP)),
+            ("e.gif", encode(&img, Gif)),
+            ("f.bmp", encode(&img, Bmp)),
+            ("g.tif", encode(&img, Tiff)),
+            ("h.tiff", encode(&img, Tiff)),
+        ];
+        for (name, bytes) in &files {
+            fs::write(dir.path().join(name), bytes).unwrap();
+        }
+        fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+
+        let found = collect_image_paths(
+            dir.path().to_str().unwrap(),
+            false,
+            &ScanOptions::default(),
+            &globset::GlobSet::empty(),
+            None,
+            |e| panic!("{}: {}", e.path, e.reason),
+        );
+        let mut names: Vec<String> = found
+            .iter()
+            .map(|(p, _)| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, files.map(|(name, _)| name.to_string()));
+        for (path, _) in &found {
+            assert_eq!(file_dimensions(path), Some((48, 32)), "{}", path.display());
+            let decoded = decode_checked(path, None, MAX_IMAGE_PIXELS).unwrap();
+            assert_ne!(compute_phash(&decoded), 0, "{}", path.display());
+        }
+    }
+    #[test]
+    fn scanning_one_folder_keeps_the_rows_of_another() {
+        test_db();
+        let _scan = SCAN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // "photos2" starts with "photos", which a plain prefix match would confuse
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("photos"), dir.path().join("photos2"));
+        for folder in [&a, &b] {
+            fs::create_dir(folder).unwrap();
+            write_images(folder, 5);
+        }
+        let app = tauri::test::mock_app();
+        let scan = |folder: &Path| {
+            let root = folder.to_string_lossy().to_string();
+            scan_roots(&[root], true, ScanOptions::default(), app.handle()).unwrap()
+        };
+        let cached = |images: &[ImageInfo]| {
+            images
+                .iter()
+                .filter(|img| cache_get(&img.path, img.modified_at, img.size).is_some())
+                .count()
+        };
+
+        let first = scan(&a);
+        assert_eq!(first.len(), 5);
+        let second = scan(&b);
+        assert_eq!(second.len(), 5);
+        assert_eq!(cached(&first), 5);
+
+        // a file deleted from the second folder is pruned on its next scan, the first
+        // folder's rows are still left alone
+        fs::remove_file(&second[0].path).unwrap();
+        assert_eq!(scan(&b).len(), 4);
+        assert_eq!(cached(&second), 4);
+        assert_eq!(cached(&first), 5);
+    }
+    // a timing comparison, too noisy for the default run. writer_commits_every_queued_row
+    // covers correctness. cargo test -- --ignored --nocapture batched_writes
+    #[test]
+    #[ignore = "benchmark"]
+    fn batched_writes_beat_one_transaction_per_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_str = dir.path().to_string_lossy();
+        init_db(&dir_str).unwrap();
+        let rows = |prefix: &str| -> Vec<ImageInfo> {
+            (0..3000)
+                .map(|i| test_row(format!("/{prefix}/{i}.jpg")))
+                .collect()
+        };
+        let (single, batch) = (rows("per-row"), rows("batched"));
+
+        // the old path: every row its own INSERT and implicit transaction
+        let mut conn = Connection::open(db_path(&dir_str)).unwrap();
+        conn.execute_batch("PRAGMA synchronous=NORMAL;").unwrap();
+        let start = std::time::Instant::now();
+        for row in &single {
+            super::write_images(&mut conn, std::slice::from_ref(row)).unwrap();
+        }
+        let per_row = start.elapsed();
+
+        let writer = spawn_writer(&dir_str).unwrap();
+        let start = std::time::Instant::now();
+        for row in batch {
+            writer.send(WriterMsg::Image(Box::new(row))).unwrap();
+        }
+        let (reply, done) = std::sync::mpsc::channel();
+        writer.send(WriterMsg::Flush(reply)).unwrap();
+        done.recv().unwrap().unwrap();
+        let batched = start.elapsed();
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM images", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 6000);
+
+        eprintln!("3000 rows: {per_row:?} one per transaction, {batched:?} batched");
+        assert!(batched < per_row, "{batched:?} vs {per_row:?}");
+    }
+    #[test]
+    fn archive_rows_survive_a_scan_without_archives() {
+        test_db();
+        let _scan = SCAN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("photos.zip");
+        let a = encode(&test_image(64, 48, 5), image::ImageFormat::Png);
+        let b = encode(&test_image(64, 48, 6), image::ImageFormat::Png);
+        write_zip(&archive, &[("a.png", &a), ("b.png", &b)]);
+        let root = dir.path().to_string_lossy().to_string();
+        let app = tauri::test::mock_app();
+        let scan = |scan_archives: bool| {
+            let options = ScanOptions {
+                scan_archives,
+                ..ScanOptions::default()
+            };
+            scan_roots(std::slice::from_ref(&root), true, options, app.handle()).unwrap()
+        };
+        let cached = |images: &[ImageInfo]| {
+            images
+                .iter()
+                .filter(|img| cache_get(&img.path, img.modified_at, img.size).is_some())
+                .count()
+        };
+
+        let entries = scan(true);
+        assert_eq!(entries.len(), 2);
+        assert!(scan(false).is_empty());
+        assert_eq!(cached(&entries), 2);
+
+        // the archive is what's checked on disk, so the entries aren't reported missing
+        let verified = verify_library(Some(root.clone()), None).unwrap();
+        assert!(verified.missing.is_empty(), "{:?}", verified.missing);
+        assert_eq!(verified.unchanged, 2);
+
+        // entries are read out of the zip for thumbnails, and refused by file moves
+        assert!(cached_thumbnail(&entries[0].path, 32).is_ok());
+        let dest = dir.path().join("moved").to_string_lossy().to_string();
+        let moved = move_files(vec![entries[0].path.clone()], dest);
+        assert_eq!(moved[0]["moved"], false);
+        assert!(moved[0]["error"]
+            .as_str()
+            .unwrap()
+            .contains("extract it first"));
+        assert_eq!(cached(&entries), 2);
+
+        // an entry taken out of the archive is pruned by the next scan that lists it
+        write_zip(&archive, &[("a.png", &a)]);
+        let rescanned = scan(true);
+        assert_eq!(rescanned.len(), 1);
+        let gone = entries
+            .iter()
+            .find(|img| img.path.ends_with("b.png"))
+            .unwrap();
+        assert!(!is_cached_path(&gone.path));
+        assert!(is_cached_path(&rescanned[0].path));
+    }
+    #[test]
+    fn moved_file_takes_its_cache_row_along() {
+        test_db();
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("a.png");
+        fs::write(
+            &from,
+            encode(&test_image(32, 32, 1), image::ImageFormat::Png),
+        )
+        .unwrap();
+        let from = from.to_string_lossy().to_string();
+        cache_set_batch(&[test_row(from.clone())]).unwrap();
+
+        let to = dir.path().join("b.png");
+        move_with_cache(&from, &to).unwrap();
+        assert!(!Path::new(&from).exists() && to.exists());
+        assert!(!is_cached_path(&from));
+        assert!(is_cached_path(&to.to_string_lossy()));
+
+        // a move that can't happen leaves the file and its row where they were
+        let nowhere = dir.path().join("missing").join("c.png");
+        assert!(move_with_cache(&to.to_string_lossy(), &nowhere).is_err());
+        assert!(to.exists() && is_cached_path(&to.to_string_lossy()));
+    }
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-import { useState } from "react";
+import { useEffect, useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import { ImageCard } from "./components/ImageCard";
@@ -8,10 +8,27 @@ import { globalCSS, t } from "./theme";
 
 export interface ExifData {
   date: number | null;
+  date_ms: number | null;
   make: string | null;
   model: string | null;
   width: number | null;
   height: number | null;
+  lat: number | null;
+  lon: number | null;
+  orientation: number | null;
+  iso: number | null;
+  f_number: number | null;
+  exposure_time: string | null;
+  focal_length: number | null;
+  lens_model: string | null;
+}
+
+export interface ImageMetadata {
+  title: string | null;
+  description: string | null;
+  keywords: string[];
+  copyright: string | null;
+  sidecar_modified_at: number | null;
 }
 
 export interface ImageInfo {
@@ -21,13 +38,37 @@ export interface ImageInfo {
   created_at: number;
   modified_at: number;
   phash: string | null;
-  sha1: string | null;
+  ahash: string | null;
+  sha256: string | null;
   exif: ExifData | null;
+  phash_rotations: string[] | null;
+  phash_mirror: string | null;
+  tile_hashes: string[] | null;
+  metadata: ImageMetadata | null;
+  place: string | null;
+  hash_algo: "dhash" | "phash" | "dhash_normalized" | "dhash_document";
+  hashes_computed: boolean;
+  metadata_level: "none" | "dimensions" | "full";
 }
 
 interface ScanProgress {
+  phase: "discovering" | "hashing" | "finalizing";
   current: number;
   total: number;
+  bytes_done: number;
+  bytes_total: number;
+}
+
+const SCAN_PHASE_LABELS: Record<ScanProgress["phase"], string> = {
+  discovering: "Discovering files…",
+  hashing: "Hashing…",
+  finalizing: "Finalizing…",
+};
+
+interface ScanError {
+  path: string;
+  kind: "IoError" | "DecodeError" | "MetadataError" | "SymlinkLoop" | "Timeout" | "TooSmall";
+  reason: string;
 }
 
 // ── Icons ─────────────────────────────────────────────────────────────────────
@@ -75,6 +116,7 @@ function App() {
   const [folderPath, setFolderPath] = useState("");
   const [currentPath, setCurrentPath] = useState<string | null>(null);
   const [error, setError] = useState("");
+  const [notice, setNotice] = useState("");
   const [showDuplicates, setShowDuplicates] = useState(false);
   const [duplicateMode, setDuplicateMode] = useState<"similar" | "exact">("similar");
   const [similarCount, setSimilarCount] = useState<number | null>(null);
@@ -85,6 +127,16 @@ function App() {
   const [selectedYears, setSelectedYears] = useState<string[]>([]);
   const [groupByYear, setGroupByYear] = useState(false);
 
+  // restore the last session's folder and scan mode
+  useEffect(() => {
+    invoke<string | null>("get_setting", { key: "last_folder" })
+      .then((folder) => { if (folder) setFolderPath(folder); })
+      .catch(console.error);
+    invoke<boolean | null>("get_setting", { key: "recursive" })
+      .then((value) => { if (value !== null) setRecursive(value); })
+      .catch(console.error);
+  }, []);
+
   // ── Helpers ────────────────────────────────────────────────────────────────
 
   const getImageYear = (img: ImageInfo): string | null => {
@@ -118,16 +170,29 @@ function App() {
 
   const handleScanFolder = async () => {
     if (!folderPath.trim()) { setError("Please enter a folder path"); return; }
-    setLoading(true); setError(""); setScanProgress(null);
+    setLoading(true); setError(""); setNotice(""); setScanProgress(null);
     setLoadingMessage("Scanning folder…");
     const unlisten = await listen<ScanProgress>("scan-progress", (e) => setScanProgress(e.payload));
+    const scanErrors: ScanError[] = [];
+    const unlistenErrors = await listen<ScanError>("scan-error", (e) => scanErrors.push(e.payload));
     try {
       const result = await invoke<ImageInfo[]>("scan_folder", { folderPath: folderPath.trim(), recursive });
       setImages(result);
       setCurrentPath(folderPath.trim());
       setSimilarCount(null); setExactCount(null);
+      invoke("set_setting", { key: "last_folder", value: folderPath.trim() }).catch(console.error);
+      invoke("set_setting", { key: "recursive", value: recursive }).catch(console.error);
+      // TooSmall and SymlinkLoop are files and folders left out on purpose, not failures
+      const count = (kind: ScanError["kind"]) => scanErrors.filter((e) => e.kind === kind).length;
+      const failed = scanErrors.length - count("TooSmall") - count("SymlinkLoop");
+      if (failed > 0) setError(`${failed} files couldn't be read or decoded`);
+      const skipped = [
+        count("TooSmall") > 0 && `${count("TooSmall")} images below the minimum size skipped`,
+        count("SymlinkLoop") > 0 && `${count("SymlinkLoop")} linked folders skipped as already scanned`,
+      ].filter(Boolean);
+      if (skipped.length > 0) setNotice(skipped.join(", "));
     } catch (e) { setError(String(e)); }
-    finally { unlisten(); setLoading(false); setLoadingMessage(""); setScanProgress(null); }
+    finally { unlisten(); unlistenErrors(); setLoading(false); setLoadingMessage(""); setScanProgress(null); }
   };
 
   const handleSimilarDuplicates = async () => {
@@ -151,7 +216,11 @@ function App() {
   };
 
   const handleDelete = async (paths: string[]) => {
-    const results = await invoke<{ path: string; deleted: boolean }[]>("delete_images", { paths });
+    // only delete files whose content still matches what was scanned
+    const expectedSha256 = Object.fromEntries(
+      images.filter((img) => paths.includes(img.path) && img.sha256).map((img) => [img.path, img.sha256])
+    );
+    const results = await invoke<{ path: string; deleted: boolean }[]>("delete_images", { paths, expectedSha256 });
     const deletedPaths = new Set(results.filter((r) => r.deleted).map((r) => r.path));
     setImages((prev) => prev.filter((img) => !deletedPaths.has(img.path)));
     setDuplicates((prev) =>
@@ -164,7 +233,7 @@ function App() {
   };
 
   const handleReset = () => {
-    setCurrentPath(null); setImages([]); setDuplicates([]);
+    setCurrentPath(null); setImages([]); setDuplicates([]); setError(""); setNotice("");
     setFolderPath(""); setSimilarCount(null); setExactCount(null);
     setShowDuplicates(false); setSelectedYears([]); setYearFilter("");
   };
@@ -245,6 +314,7 @@ function App() {
               </label>
 
               {error && <div className="error-banner" style={{ marginBottom: 16 }}>{error}</div>}
+              {notice && <div className="notice-banner" style={{ marginBottom: 16 }}>{notice}</div>}
 
               <button
                 className="btn btn-accent btn-lg btn-full"
@@ -267,9 +337,11 @@ function App() {
   // ── Loading screen ─────────────────────────────────────────────────────────
 
   if (loading) {
-    const pct = scanProgress && scanProgress.total > 0
-      ? Math.round((scanProgress.current / scanProgress.total) * 100)
-      : null;
+    // weight by size when known so a few huge RAWs don't make the bar jump
+    const pct = !scanProgress || scanProgress.total === 0 ? null
+      : scanProgress.bytes_total > 0
+        ? Math.round((scanProgress.bytes_done / scanProgress.bytes_total) * 100)
+        : Math.round((scanProgress.current / scanProgress.total) * 100);
 
     return (
       <>
@@ -280,7 +352,9 @@ function App() {
         }}>
           <IconSpinner />
           <div style={{ textAlign: "center" }}>
-            <p style={{ fontSize: 14, fontWeight: 600, color: t.text }}>{loadingMessage}</p>
+            <p style={{ fontSize: 14, fontWeight: 600, color: t.text }}>
+              {scanProgress ? SCAN_PHASE_LABELS[scanProgress.phase] : loadingMessage}
+            </p>
 
             {scanProgress && scanProgress.total > 0 ? (
               <>
@@ -386,6 +460,9 @@ function App() {
       {/* ── Page content ── */}
       <div style={{ padding: "16px 20px 80px" }}>
 
+        {error && <div className="error-banner" style={{ marginBottom: 16 }}>{error}</div>}
+        {notice && <div className="notice-banner" style={{ marginBottom: 16 }}>{notice}</div>}
+
         {!showDuplicates && (
           <FilterBar
             year={yearFilter}
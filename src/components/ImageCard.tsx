@@ -25,6 +25,11 @@ export function ImageCard({ image }: { image: ImageInfo }) {
     invoke("open_image", { path: image.path }).catch(console.error);
   };
 
+  const handleReveal = (e: React.MouseEvent) => {
+    e.preventDefault();
+    invoke("reveal_in_folder", { path: image.path }).catch(console.error);
+  };
+
   const src = convertFileSrc(image.path);
   const date = getDate();
   const hasDimensions = image.exif?.width && image.exif?.height;
@@ -33,6 +38,7 @@ export function ImageCard({ image }: { image: ImageInfo }) {
     <div
       className="img-card"
       onClick={handleOpen}
+      onContextMenu={handleReveal}
       onMouseEnter={() => setHovered(true)}
       onMouseLeave={() => setHovered(false)}
       title={image.path}
@@ -208,6 +208,13 @@ export const globalCSS = `
     padding: 10px 14px; font-size: 13px;
   }
 
+  /* Notice banner, for things the user should know that aren't failures */
+  .notice-banner {
+    background: var(--accent-soft); color: var(--text2);
+    border: 1px solid var(--accent-bd); border-radius: 8px;
+    padding: 10px 14px; font-size: 13px;
+  }
+
   /* Section header */
   .section-header {
     display: flex; align-items: center; gap: 10px;